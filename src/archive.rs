@@ -1,8 +1,19 @@
+use crate::exe_header::HEADER_SIZE;
+use crate::rel_path::RelPath;
+use anyhow::Context;
+use std::collections::BTreeMap;
 use std::fmt::{Display, Formatter};
+use std::fs;
+use std::fs::File;
+use std::io::{BufReader, Cursor, Read};
+use std::path::Path;
 
-#[derive(ordinal_map::Ordinal)]
+#[derive(ordinal_map::Ordinal, Clone, Copy, PartialEq, Eq)]
 pub(crate) enum ArchiveFormat {
     Zip,
+    TarGz,
+    TarXz,
+    TarBz2,
 }
 
 impl Display for ArchiveFormat {
@@ -13,33 +24,361 @@ impl Display for ArchiveFormat {
 
 impl ArchiveFormat {
     pub(crate) fn from_file_path(path: &str) -> anyhow::Result<Self> {
-        if path.ends_with(".zip") {
-            Ok(ArchiveFormat::Zip)
-        } else {
-            Err(anyhow::anyhow!(
-                "Cannot determine archive format for `{path}`"
-            ))
-        }
+        let extensions = [
+            (".zip", ArchiveFormat::Zip),
+            (".tar.gz", ArchiveFormat::TarGz),
+            (".tgz", ArchiveFormat::TarGz),
+            (".tar.xz", ArchiveFormat::TarXz),
+            (".txz", ArchiveFormat::TarXz),
+            (".tar.bz2", ArchiveFormat::TarBz2),
+            (".tbz2", ArchiveFormat::TarBz2),
+        ];
+        extensions
+            .into_iter()
+            .find(|(extension, _)| path.ends_with(extension))
+            .map(|(_, format)| format)
+            .with_context(|| format!("Cannot determine archive format for `{path}`"))
     }
 
     pub(crate) fn command(&self) -> &'static str {
         match self {
             ArchiveFormat::Zip => "unzip",
+            ArchiveFormat::TarGz | ArchiveFormat::TarXz | ArchiveFormat::TarBz2 => "tar",
         }
     }
 
     pub(crate) fn test_command(&self) -> &'static str {
         match self {
             ArchiveFormat::Zip => "unzip -v",
+            ArchiveFormat::TarGz | ArchiveFormat::TarXz | ArchiveFormat::TarBz2 => "tar --version",
+        }
+    }
+
+    /// Shell command which extracts `archive` into `dest`, which does not exist yet.
+    /// Arguments are quoted shell words.
+    pub(crate) fn extract_command(&self, archive: &str, dest: &str) -> String {
+        let tar_flag = match self {
+            // `-V` to keep trailing `;` in file names, which unzip treats as VMS versions.
+            ArchiveFormat::Zip => return format!("unzip -V -qq {archive} -d {dest} >/dev/null"),
+            ArchiveFormat::TarGz => "z",
+            ArchiveFormat::TarXz => "J",
+            ArchiveFormat::TarBz2 => "j",
+        };
+        format!("mkdir {dest} && tar -x{tar_flag}f {archive} -C {dest} --no-same-owner")
+    }
+
+    /// Uncompressed tar stream of a tar archive.
+    fn tar_reader<'a, R: Read + 'a>(&self, archive: R) -> Box<dyn Read + 'a> {
+        match self {
+            ArchiveFormat::Zip => unreachable!("zip is not a tar archive"),
+            ArchiveFormat::TarGz => Box::new(flate2::read::MultiGzDecoder::new(archive)),
+            ArchiveFormat::TarXz => Box::new(lzma_rust2::XzReader::new(archive, true)),
+            ArchiveFormat::TarBz2 => Box::new(bzip2_rs::DecoderReader::new(archive)),
+        }
+    }
+
+    /// Extract `archive` into `dest` natively, like the generated script does
+    /// with `extract_command`.
+    pub(crate) fn extract(&self, archive: &Path, dest: &Path) -> anyhow::Result<()> {
+        let file = File::open(archive)
+            .with_context(|| format!("Failed to open `{}`", archive.display()))?;
+        let result = match self {
+            ArchiveFormat::Zip => zip::ZipArchive::new(BufReader::new(file))
+                .and_then(|mut zip| zip.extract(dest))
+                .map_err(anyhow::Error::from),
+            ArchiveFormat::TarGz | ArchiveFormat::TarXz | ArchiveFormat::TarBz2 => {
+                fs::create_dir(dest)
+                    .and_then(|()| {
+                        tar::Archive::new(self.tar_reader(BufReader::new(file))).unpack(dest)
+                    })
+                    .map_err(anyhow::Error::from)
+            }
+        };
+        result.with_context(|| format!("Failed to extract `{}`", archive.display()))
+    }
+
+    /// Fail unless `path` in the archive is a regular file or a symlink to one,
+    /// executable if `executable` is set, suggesting close matches if it is missing.
+    ///
+    /// `path` is relative to the directory left after stripping `strip_components` levels
+    /// of single directories, like the generated script does.
+    ///
+    /// Returns the first `HEADER_SIZE` bytes of the file.
+    pub(crate) fn check_path(
+        &self,
+        archive: &[u8],
+        strip_components: u32,
+        path: &RelPath,
+        executable: bool,
+    ) -> anyhow::Result<Vec<u8>> {
+        let read_error = || format!("Failed to read {} archive", self.command());
+        match self {
+            ArchiveFormat::Zip => {
+                let mut zip =
+                    zip::ZipArchive::new(Cursor::new(archive)).with_context(read_error)?;
+                let entries = zip_entries(&mut zip).with_context(read_error)?;
+                let name = resolve_path(&entries, strip_components, path.as_str(), executable)?;
+                let mut header = Vec::new();
+                zip.by_name(&name)?
+                    .take(HEADER_SIZE as u64)
+                    .read_to_end(&mut header)
+                    .with_context(|| format!("Failed to read `{name}`"))?;
+                Ok(header)
+            }
+            ArchiveFormat::TarGz | ArchiveFormat::TarXz | ArchiveFormat::TarBz2 => {
+                let entries = tar_entries(self.tar_reader(archive)).with_context(read_error)?;
+                let name = resolve_path(&entries, strip_components, path.as_str(), executable)?;
+                tar_header(self.tar_reader(archive), &name)
+                    .with_context(|| format!("Failed to read `{name}`"))
+            }
+        }
+    }
+}
+
+/// Like `ELOOP`.
+const MAX_SYMLINKS: u32 = 40;
+/// File type bits of unix mode in zip entries, independent of the host.
+const S_IFMT: u32 = 0o170000;
+const S_IFLNK: u32 = 0o120000;
+
+/// Archive entry, as far as `check_path` is concerned.
+enum Entry {
+    Dir,
+    File { mode: u32 },
+    Symlink(String),
+}
+
+/// Entries by name, with directories named with a trailing `/` like in zip archives.
+type Entries = BTreeMap<String, Entry>;
+
+fn zip_entries(zip: &mut zip::ZipArchive<Cursor<&[u8]>>) -> anyhow::Result<Entries> {
+    let mut entries = Entries::new();
+    for index in 0..zip.len() {
+        let mut file = zip.by_index(index)?;
+        let mode = file.unix_mode().unwrap_or(0);
+        let entry = if file.is_dir() {
+            Entry::Dir
+        } else if mode & S_IFMT == S_IFLNK {
+            let mut target = String::new();
+            file.read_to_string(&mut target)
+                .with_context(|| format!("Failed to read symlink `{}`", file.name()))?;
+            Entry::Symlink(target)
+        } else {
+            Entry::File { mode }
+        };
+        entries.insert(file.name().to_owned(), entry);
+    }
+    Ok(entries)
+}
+
+/// Name of a tar entry in the form zip uses: without `./`, directories with a trailing `/`.
+fn tar_entry_name(entry: &tar::Entry<impl Read>) -> anyhow::Result<String> {
+    let path = entry.path()?;
+    let path = path.to_str().context("Non-UTF-8 path in tar archive")?;
+    let mut name = path
+        .trim_start_matches("./")
+        .trim_end_matches('/')
+        .to_owned();
+    if entry.header().entry_type().is_dir() && !name.is_empty() {
+        name.push('/');
+    }
+    Ok(name)
+}
+
+fn tar_entries(tar: impl Read) -> anyhow::Result<Entries> {
+    let mut entries = Entries::new();
+    for entry in tar::Archive::new(tar).entries()? {
+        let entry = entry?;
+        let name = tar_entry_name(&entry)?;
+        if name.is_empty() || name == "." {
+            continue;
+        }
+        let header = entry.header();
+        let entry = match header.entry_type() {
+            tar::EntryType::Directory => Entry::Dir,
+            tar::EntryType::Symlink => {
+                let target = entry.link_name()?.context("Symlink without target")?;
+                let target = target.to_str().context("Non-UTF-8 symlink target")?;
+                Entry::Symlink(target.to_owned())
+            }
+            _ => Entry::File {
+                mode: header.mode()?,
+            },
+        };
+        entries.insert(name, entry);
+    }
+    Ok(entries)
+}
+
+fn tar_header(tar: impl Read, name: &str) -> anyhow::Result<Vec<u8>> {
+    for entry in tar::Archive::new(tar).entries()? {
+        let entry = entry?;
+        if tar_entry_name(&entry)? == name {
+            let mut header = Vec::new();
+            entry.take(HEADER_SIZE as u64).read_to_end(&mut header)?;
+            return Ok(header);
+        }
+    }
+    Err(anyhow::anyhow!("`{name}` disappeared from the archive"))
+}
+
+/// Name of the file `path` resolves to in the archive; see `ArchiveFormat::check_path`.
+fn resolve_path(
+    entries: &Entries,
+    strip_components: u32,
+    path: &str,
+    executable: bool,
+) -> anyhow::Result<String> {
+    let names = || entries.keys().map(String::as_str);
+    let mut prefix = String::new();
+    for _ in 0..strip_components {
+        let top = single_dir(names(), &prefix)?;
+        prefix = format!("{prefix}{top}/");
+    }
+    let mut current = path.to_owned();
+    for _ in 0..MAX_SYMLINKS {
+        if let Some(resolved) = resolve_parent_symlink(entries, &prefix, &current)? {
+            current = resolved;
+            continue;
+        }
+        let name = format!("{prefix}{current}");
+        let Some(entry) = entries.get(&name) else {
+            if entries.contains_key(&format!("{name}/")) {
+                return Err(anyhow::anyhow!("`{current}` is a directory"));
+            }
+            let mut message = format!("Archive does not contain `{name}`");
+            let matches = close_matches(
+                &current,
+                names().filter_map(|name| name.strip_prefix(&prefix)),
+            );
+            if !matches.is_empty() {
+                message.push_str(&format!("; did you mean `{}`?", matches.join("`, `")));
+            }
+            return Err(anyhow::anyhow!(message));
+        };
+        match entry {
+            Entry::Dir => return Err(anyhow::anyhow!("`{current}` is a directory")),
+            Entry::Symlink(target) => current = symlink_target(&current, target)?,
+            Entry::File { mode } => {
+                if executable && mode & 0o111 == 0 {
+                    return Err(anyhow::anyhow!(
+                        "`{current}` is not executable; use `interpreter=` to run it with an interpreter"
+                    ));
+                }
+                return Ok(name);
+            }
+        }
+    }
+    Err(anyhow::anyhow!("Too many levels of symlinks at `{path}`"))
+}
+
+/// `path` with its outermost symlinked directory replaced by the symlink target,
+/// or `None` if no directory of `path` is a symlink.
+fn resolve_parent_symlink(
+    entries: &Entries,
+    prefix: &str,
+    path: &str,
+) -> anyhow::Result<Option<String>> {
+    for (i, _) in path.match_indices('/') {
+        let (dir, rest) = (&path[..i], &path[i + 1..]);
+        if let Some(Entry::Symlink(target)) = entries.get(&format!("{prefix}{dir}")) {
+            let target = symlink_target(dir, target)?;
+            return Ok(Some(if target.is_empty() {
+                rest.to_owned()
+            } else {
+                format!("{target}/{rest}")
+            }));
         }
     }
+    Ok(None)
+}
+
+/// The only entry under `prefix`, which must be a directory.
+fn single_dir<'a>(names: impl Iterator<Item = &'a str>, prefix: &str) -> anyhow::Result<&'a str> {
+    let mut entries = BTreeMap::new();
+    for name in names {
+        let Some(rest) = name.strip_prefix(prefix).filter(|rest| !rest.is_empty()) else {
+            continue;
+        };
+        let (first, is_dir) = match rest.split_once('/') {
+            Some((first, _)) => (first, true),
+            None => (rest, false),
+        };
+        *entries.entry(first).or_insert(false) |= is_dir;
+    }
+    let mut entries = entries.into_iter();
+    match (entries.next(), entries.next()) {
+        (Some((dir, true)), None) => Ok(dir),
+        _ => Err(anyhow::anyhow!(
+            "Cannot strip components: `{prefix}` in the archive is not a single directory"
+        )),
+    }
+}
+
+/// Path in the archive a symlink at `link` points to.
+pub(crate) fn symlink_target(link: &str, target: &str) -> anyhow::Result<String> {
+    if target.starts_with('/') {
+        return Err(anyhow::anyhow!(
+            "`{link}` is a symlink to absolute path `{target}`"
+        ));
+    }
+    let mut components: Vec<&str> = link.split('/').collect();
+    components.pop();
+    for component in target.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                if components.pop().is_none() {
+                    return Err(anyhow::anyhow!(
+                        "`{link}` is a symlink to `{target}` outside of the archive"
+                    ));
+                }
+            }
+            component => components.push(component),
+        }
+    }
+    Ok(components.join("/"))
+}
+
+/// Files in the archive with the same file name or a name at a small edit distance.
+fn close_matches<'a>(path: &str, names: impl Iterator<Item = &'a str>) -> Vec<&'a str> {
+    let file_name = path.rsplit('/').next().unwrap();
+    let max_distance = (path.chars().count() / 3).max(2);
+    let mut matches: Vec<(usize, &str)> = names
+        .filter(|name| !name.ends_with('/'))
+        .filter_map(|name| {
+            let distance = edit_distance(path, name);
+            let same_file_name = name.rsplit('/').next() == Some(file_name);
+            (distance <= max_distance || same_file_name).then_some((distance, name))
+        })
+        .collect();
+    matches.sort();
+    matches.into_iter().take(3).map(|(_, name)| name).collect()
+}
+
+/// Levenshtein distance.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitute = prev[j] + usize::from(ca != *cb);
+            cur.push(substitute.min(prev[j + 1] + 1).min(cur[j] + 1));
+        }
+        prev = cur;
+    }
+    prev[b.len()]
 }
 
 #[cfg(test)]
 mod tests {
     use crate::archive::ArchiveFormat;
+    use crate::rel_path::RelPath;
     use crate::testutil::assert_shell_ok;
     use ordinal_map::Ordinal;
+    use std::io::{Cursor, Write};
+    use zip::write::SimpleFileOptions;
 
     #[test]
     fn test_test_command() {
@@ -47,4 +386,175 @@ mod tests {
             assert_shell_ok(archive.test_command());
         }
     }
+
+    #[test]
+    fn test_check_path() {
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let exe = SimpleFileOptions::default().unix_permissions(0o755);
+        zip.add_directory("tool-1.0/bin/", exe).unwrap();
+        zip.start_file("tool-1.0/bin/tool", exe).unwrap();
+        zip.write_all(b"#!/bin/sh\n").unwrap();
+        zip.start_file(
+            "tool-1.0/README",
+            SimpleFileOptions::default().unix_permissions(0o644),
+        )
+        .unwrap();
+        zip.add_symlink("tool-1.0/tool", "bin/tool", exe).unwrap();
+        zip.add_symlink("tool-1.0/readme", "./README", exe).unwrap();
+        zip.add_symlink("tool-1.0/escape", "../../etc/passwd", exe)
+            .unwrap();
+        zip.add_symlink("tool-1.0/loop", "loop", exe).unwrap();
+        let archive = zip.finish().unwrap().into_inner();
+
+        let check = |path: &str| {
+            ArchiveFormat::Zip.check_path(&archive, 0, RelPath::new(path).unwrap(), true)
+        };
+        check("tool-1.0/bin/tool").unwrap();
+        check("tool-1.0/tool").unwrap();
+        assert!(check("tool-1.0/README").is_err());
+        assert!(check("tool-1.0/readme").is_err());
+        ArchiveFormat::Zip
+            .check_path(&archive, 0, RelPath::new("tool-1.0/readme").unwrap(), false)
+            .unwrap();
+        assert!(check("tool-1.0/bin").is_err());
+        assert!(check("tool-1.0/escape").is_err());
+        assert!(check("tool-1.0/loop").is_err());
+
+        let error = format!("{:#}", check("bin/tool").unwrap_err());
+        assert!(
+            error.contains("did you mean `tool-1.0/tool`, `tool-1.0/bin/tool`?"),
+            "{error}"
+        );
+        let error = format!("{:#}", check("tool-1.0/bin/tol").unwrap_err());
+        assert!(
+            error.contains("did you mean `tool-1.0/bin/tool`"),
+            "{error}"
+        );
+
+        let check_strip = |strip_components, path: &str| {
+            ArchiveFormat::Zip.check_path(
+                &archive,
+                strip_components,
+                RelPath::new(path).unwrap(),
+                true,
+            )
+        };
+        check_strip(1, "bin/tool").unwrap();
+        check_strip(1, "tool").unwrap();
+        let error = format!("{:#}", check_strip(1, "tool-1.0/bin/tool").unwrap_err());
+        assert!(error.contains("did you mean `bin/tool`"), "{error}");
+        // `tool-1.0` has more than one entry.
+        assert!(check_strip(2, "tool").is_err());
+    }
+
+    #[test]
+    fn test_check_path_symlinked_dir() {
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let exe = SimpleFileOptions::default().unix_permissions(0o755);
+        zip.start_file("libexec/tool", exe).unwrap();
+        zip.write_all(b"#!/bin/sh\n").unwrap();
+        zip.add_symlink("bin", "libexec", exe).unwrap();
+        zip.add_symlink("share/bin", "../bin", exe).unwrap();
+        zip.add_symlink("root", ".", exe).unwrap();
+        zip.add_symlink("lib", "..", exe).unwrap();
+        zip.add_symlink("a", "b", exe).unwrap();
+        zip.add_symlink("b", "a", exe).unwrap();
+        let archive = zip.finish().unwrap().into_inner();
+
+        let check = |path: &str| {
+            ArchiveFormat::Zip.check_path(&archive, 0, RelPath::new(path).unwrap(), true)
+        };
+        check("bin/tool").unwrap();
+        check("share/bin/tool").unwrap();
+        check("root/bin/tool").unwrap();
+        let error = format!("{:#}", check("lib/tool").unwrap_err());
+        assert!(error.contains("outside of the archive"), "{error}");
+        let error = format!("{:#}", check("a/tool").unwrap_err());
+        assert!(error.contains("Too many levels of symlinks"), "{error}");
+    }
+
+    #[test]
+    fn test_check_tar_path() {
+        let mut tar = tar::Builder::new(Vec::new());
+        let mut append = |name: &str, entry_type, mode, content: &[u8], link: Option<&str>| {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(entry_type);
+            header.set_mode(mode);
+            header.set_size(content.len() as u64);
+            match link {
+                Some(link) => tar.append_link(&mut header, name, link).unwrap(),
+                None => tar.append_data(&mut header, name, content).unwrap(),
+            }
+        };
+        let symlink = tar::EntryType::Symlink;
+        append("./tool-1.0/", tar::EntryType::Directory, 0o755, b"", None);
+        append(
+            "./tool-1.0/bin/",
+            tar::EntryType::Directory,
+            0o755,
+            b"",
+            None,
+        );
+        append(
+            "./tool-1.0/bin/tool",
+            tar::EntryType::Regular,
+            0o755,
+            b"#!/bin/sh\n",
+            None,
+        );
+        append(
+            "./tool-1.0/README",
+            tar::EntryType::Regular,
+            0o644,
+            b"",
+            None,
+        );
+        append("./tool-1.0/tool", symlink, 0o777, b"", Some("bin/tool"));
+        append("./tool-1.0/readme", symlink, 0o777, b"", Some("./README"));
+        append(
+            "./tool-1.0/escape",
+            symlink,
+            0o777,
+            b"",
+            Some("../../etc/passwd"),
+        );
+        append("./tool-1.0/loop", symlink, 0o777, b"", Some("loop"));
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        gz.write_all(&tar.into_inner().unwrap()).unwrap();
+        let archive = gz.finish().unwrap();
+
+        let check = |strip_components, path: &str, executable| {
+            ArchiveFormat::TarGz.check_path(
+                &archive,
+                strip_components,
+                RelPath::new(path).unwrap(),
+                executable,
+            )
+        };
+        assert_eq!(
+            b"#!/bin/sh\n".as_slice(),
+            check(0, "tool-1.0/bin/tool", true).unwrap()
+        );
+        check(0, "tool-1.0/tool", true).unwrap();
+        assert!(check(0, "tool-1.0/README", true).is_err());
+        assert!(check(0, "tool-1.0/readme", true).is_err());
+        check(0, "tool-1.0/readme", false).unwrap();
+        assert!(check(0, "tool-1.0/bin", true).is_err());
+        assert!(check(0, "tool-1.0/escape", true).is_err());
+        assert!(check(0, "tool-1.0/loop", true).is_err());
+        let error = format!("{:#}", check(0, "tool-1.0/bin/tol", true).unwrap_err());
+        assert!(
+            error.contains("did you mean `tool-1.0/bin/tool`"),
+            "{error}"
+        );
+
+        check(1, "bin/tool", true).unwrap();
+        check(1, "tool", true).unwrap();
+        let error = format!("{:#}", check(1, "tool-1.0/bin/tool", true).unwrap_err());
+        assert!(error.contains("did you mean `bin/tool`"), "{error}");
+
+        assert!(ArchiveFormat::TarXz
+            .check_path(&archive, 0, RelPath::new("tool-1.0/tool").unwrap(), true)
+            .is_err());
+    }
 }
@@ -0,0 +1,370 @@
+//! Cache layout shared with generated scripts (see `gen.rs`).
+
+use crate::digest::Digest;
+use crate::gen::{GenOptions, CACHE_DIR_VAR};
+use crate::os::env_var_non_empty;
+use crate::os::Os;
+use anyhow::Context;
+use std::collections::BTreeSet;
+use std::env;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+pub(crate) struct CacheDirs {
+    /// `commaslash_dir` in generated scripts.
+    pub(crate) main: PathBuf,
+    /// `main` is set explicitly with `COMMASLASH_CACHE_DIR`, so it is always used.
+    pub(crate) main_overridden: bool,
+    /// `backup_commaslash_dir` in generated scripts.
+    pub(crate) backup: PathBuf,
+}
+
+impl CacheDirs {
+    pub(crate) fn from_env(os: &Os, options: &GenOptions) -> anyhow::Result<CacheDirs> {
+        let tmp = env_var_non_empty("TMPDIR").unwrap_or_else(|| "/tmp".to_owned());
+        let backup = Path::new(&tmp).join(format!("commaslash-{}", euid()));
+        if let Some(main) = env_var_non_empty(CACHE_DIR_VAR) {
+            return Ok(CacheDirs {
+                main: PathBuf::from(main),
+                main_overridden: true,
+                backup,
+            });
+        }
+        let cache_dir = match &options.cache_dir_expr {
+            Some(cache_dir_expr) => expand_expr(cache_dir_expr)?,
+            None => os.cache_dir()?,
+        };
+        Ok(CacheDirs {
+            main: cache_dir.join("commaslash"),
+            main_overridden: false,
+            backup,
+        })
+    }
+
+    /// Both directories, in the order the fast path checks them.
+    pub(crate) fn all(&self) -> [&Path; 2] {
+        [&self.main, &self.backup]
+    }
+
+    /// Directory where new installs are placed.
+    ///
+    /// Like in generated scripts, if we don't own the main directory
+    /// (or its closest existing ancestor), we use the backup directory.
+    pub(crate) fn writable(&self) -> &Path {
+        if self.main_overridden {
+            return &self.main;
+        }
+        let mut dir = self.main.as_path();
+        loop {
+            match dir.metadata() {
+                Ok(meta) if meta.uid() == euid() => return &self.main,
+                Ok(_) => return &self.backup,
+                Err(_) => match dir.parent() {
+                    Some(parent) => dir = parent,
+                    None => return &self.main,
+                },
+            }
+        }
+    }
+}
+
+/// Commaslash dirs of the default cache and of the caches of scripts generated
+/// with `--cache-dir-expr`, given the options of the scripts, without duplicates.
+///
+/// Caches of scripts whose expression cannot be expanded are skipped with a warning.
+pub(crate) fn commaslash_dirs<'a>(
+    os: &Os,
+    script_options: impl IntoIterator<Item = &'a GenOptions>,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let mut dirs = Vec::new();
+    let default = CacheDirs::from_env(os, &GenOptions::default())?;
+    let mut add = |cache_dirs: &CacheDirs| {
+        for dir in cache_dirs.all() {
+            if !dirs.iter().any(|d| d == dir) {
+                dirs.push(dir.to_owned());
+            }
+        }
+    };
+    add(&default);
+    for options in script_options {
+        let Some(cache_dir_expr) = &options.cache_dir_expr else {
+            continue;
+        };
+        match CacheDirs::from_env(os, options) {
+            Ok(cache_dirs) => add(&cache_dirs),
+            Err(e) => eprintln!("warning: skipping cache dir `{cache_dir_expr}`: {e:#}"),
+        }
+    }
+    Ok(dirs)
+}
+
+/// Expand `$VAR` and `${VAR}` in a cache dir expression the same way generated scripts do.
+///
+/// Expressions are read from scanned scripts, so anything else the shell would evaluate,
+/// like command substitution, is rejected instead of being run.
+fn expand_expr(expr: &str) -> anyhow::Result<PathBuf> {
+    let unsupported =
+        || anyhow::anyhow!("Cannot expand `{expr}`: only `$VAR` and `${{VAR}}` are supported");
+    let mut value = String::new();
+    let mut chars = expr.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '`' => return Err(unsupported()),
+            // Backslash escapes only these in double quotes.
+            '\\' => match chars.next_if(|c| matches!(c, '$' | '`' | '"' | '\\')) {
+                Some(c) => value.push(c),
+                None => value.push('\\'),
+            },
+            '$' => {
+                let braced = chars.next_if_eq(&'{').is_some();
+                let mut name = String::new();
+                while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+                    name.push(c);
+                }
+                if name.is_empty()
+                    || name.starts_with(|c: char| c.is_ascii_digit())
+                    || (braced && chars.next() != Some('}'))
+                {
+                    return Err(unsupported());
+                }
+                value.push_str(&env::var(&name).unwrap_or_default());
+            }
+            c => value.push(c),
+        }
+    }
+    if value.is_empty() {
+        return Err(anyhow::anyhow!("`{expr}` expanded to an empty string"));
+    }
+    Ok(PathBuf::from(value))
+}
+
+fn euid() -> u32 {
+    // SAFETY: always safe.
+    unsafe { libc::geteuid() }
+}
+
+/// `install_dir` in generated scripts.
+pub(crate) fn install_dir(commaslash_dir: &Path, digest: Digest) -> PathBuf {
+    commaslash_dir.join(digest.cache_name())
+}
+
+/// `$install_dir.lock` in generated scripts.
+pub(crate) fn lock_path(commaslash_dir: &Path, digest: Digest) -> PathBuf {
+    commaslash_dir.join(format!("{}.lock", digest.cache_name()))
+}
+
+/// `$install_dir.temp` in generated scripts.
+pub(crate) fn temp_dir(commaslash_dir: &Path, digest: Digest) -> PathBuf {
+    commaslash_dir.join(format!("{}.temp", digest.cache_name()))
+}
+
+/// `$install_dir.manifest` in generated scripts, see `manifest.rs`.
+pub(crate) fn manifest_path(commaslash_dir: &Path, digest: Digest) -> PathBuf {
+    commaslash_dir.join(format!("{}.manifest", digest.cache_name()))
+}
+
+/// Marker whose mtime is the last recorded use of `install_dir`.
+///
+/// Generated scripts touch it on the first use after each gc, see [`used_stamp_path`].
+pub(crate) fn used_marker_path(commaslash_dir: &Path, digest: Digest) -> PathBuf {
+    commaslash_dir.join(format!("{}.used", digest.cache_name()))
+}
+
+/// Stamp whose mtime is the last gc of `commaslash_dir`, created by installs if missing.
+///
+/// The fast path touches a use marker only if it is not newer than the stamp,
+/// so a fresh marker costs one `test -nt`, which does not fork.
+pub(crate) fn used_stamp_path(commaslash_dir: &Path) -> PathBuf {
+    commaslash_dir.join("used.stamp")
+}
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Directory with downloaded archives named like install dirs.
+pub(crate) fn archives_dir(commaslash_dir: &Path) -> PathBuf {
+    commaslash_dir.join("archives")
+}
+
+/// `$commaslash_dir/archives/<cache name>` in generated scripts.
+pub(crate) fn archive_path(commaslash_dir: &Path, digest: Digest) -> PathBuf {
+    archives_dir(commaslash_dir).join(digest.cache_name())
+}
+
+/// Everything stored for one digest in one `commaslash_dir`:
+/// install dir, its manifest, lock file, temp dir, use markers and stored archive,
+/// any of which may be missing.
+pub(crate) struct CacheEntry {
+    pub(crate) commaslash_dir: PathBuf,
+    pub(crate) digest: Digest,
+}
+
+impl CacheEntry {
+    pub(crate) fn install_dir(&self) -> PathBuf {
+        install_dir(&self.commaslash_dir, self.digest)
+    }
+
+    pub(crate) fn lock_path(&self) -> PathBuf {
+        lock_path(&self.commaslash_dir, self.digest)
+    }
+
+    pub(crate) fn temp_dir(&self) -> PathBuf {
+        temp_dir(&self.commaslash_dir, self.digest)
+    }
+
+    pub(crate) fn archive_path(&self) -> PathBuf {
+        archive_path(&self.commaslash_dir, self.digest)
+    }
+
+    pub(crate) fn manifest_path(&self) -> PathBuf {
+        manifest_path(&self.commaslash_dir, self.digest)
+    }
+
+    pub(crate) fn used_marker_path(&self) -> PathBuf {
+        used_marker_path(&self.commaslash_dir, self.digest)
+    }
+
+    /// Temp file used while storing an archive.
+    pub(crate) fn archive_temp_path(&self) -> PathBuf {
+        self.archive_path().with_extension("temp")
+    }
+
+    /// Take the same lock generated scripts take while installing.
+    /// Returns `None` if the lock is held by another process.
+    pub(crate) fn try_lock(&self) -> anyhow::Result<Option<File>> {
+        let lock_path = self.lock_path();
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&lock_path)
+            .with_context(|| format!("Failed to open `{}`", lock_path.display()))?;
+        match file.try_lock() {
+            Ok(()) => Ok(Some(file)),
+            Err(TryLockError::WouldBlock) => Ok(None),
+            Err(TryLockError::Error(e)) => {
+                Err(e).with_context(|| format!("Failed to lock `{}`", lock_path.display()))
+            }
+        }
+    }
+
+    /// When the install was last used according to the `.used` marker,
+    /// otherwise when it was installed, or when the archive was stored if not installed.
+    pub(crate) fn last_used(&self) -> Option<SystemTime> {
+        if let Ok(modified) = self
+            .used_marker_path()
+            .metadata()
+            .and_then(|m| m.modified())
+        {
+            return Some(modified);
+        }
+        [self.install_dir(), self.archive_path()]
+            .iter()
+            .find_map(|path| path.metadata().and_then(|m| m.modified()).ok())
+    }
+
+    /// One line with install dir, size and last use, as printed by subcommands.
+    pub(crate) fn summary(&self, now: SystemTime) -> String {
+        let last_used = match self.last_used() {
+            Some(last_used) => {
+                let age = now.duration_since(last_used).unwrap_or_default();
+                format!("{} days ago", age.as_secs() / DAY.as_secs())
+            }
+            None => "never".to_owned(),
+        };
+        format!(
+            "{}  {:>12}  last used {}",
+            self.install_dir().display(),
+            self.size(),
+            last_used
+        )
+    }
+
+    /// Total size of the install and the stored archive.
+    pub(crate) fn size(&self) -> u64 {
+        disk_size(&self.install_dir()) + disk_size(&self.archive_path())
+    }
+}
+
+/// Size of a file or directory, not following symlinks. Missing files have zero size.
+pub(crate) fn disk_size(path: &Path) -> u64 {
+    let Ok(meta) = path.symlink_metadata() else {
+        return 0;
+    };
+    if !meta.is_dir() {
+        return meta.len();
+    }
+    let Ok(read_dir) = path.read_dir() else {
+        return 0;
+    };
+    read_dir
+        .filter_map(|e| e.ok())
+        .map(|e| disk_size(&e.path()))
+        .sum()
+}
+
+/// Parse `<cache name>` or `<cache name>.<suffix>` for files stored next to the install.
+fn parse_entry_name(name: &str) -> Option<Digest> {
+    let cache_name = [".lock", ".temp", ".manifest", ".used"]
+        .iter()
+        .find_map(|suffix| name.strip_suffix(suffix))
+        .unwrap_or(name);
+    Digest::from_cache_name(cache_name)
+}
+
+/// All entries in `commaslash_dir`, sorted by digest. Missing dir has no entries.
+pub(crate) fn list_entries(commaslash_dir: &Path) -> anyhow::Result<Vec<CacheEntry>> {
+    let mut digests = BTreeSet::new();
+    for dir in [commaslash_dir.to_owned(), archives_dir(commaslash_dir)] {
+        let read_dir = match dir.read_dir() {
+            Ok(read_dir) => read_dir,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e).with_context(|| format!("Failed to read `{}`", dir.display())),
+        };
+        for entry in read_dir {
+            let name = entry?.file_name();
+            if let Some(digest) = name.to_str().and_then(parse_entry_name) {
+                digests.insert(digest);
+            }
+        }
+    }
+    Ok(digests
+        .into_iter()
+        .map(|digest| CacheEntry {
+            commaslash_dir: commaslash_dir.to_owned(),
+            digest,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cache::expand_expr;
+    use std::env;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_expand_expr() {
+        let home = env::var("HOME").unwrap();
+        assert_eq!(
+            PathBuf::from(format!("{home}/ci-cache")),
+            expand_expr("$HOME/ci-cache").unwrap()
+        );
+        assert_eq!(
+            PathBuf::from(format!("{home}_x/$y")),
+            expand_expr(r"${HOME}_x/\$y").unwrap()
+        );
+        for expr in [
+            "$(touch x)",
+            "`touch x`",
+            "${HOME:-/tmp}",
+            "$1",
+            "$",
+            "${HOME",
+        ] {
+            assert!(expand_expr(expr).is_err(), "{expr}");
+        }
+    }
+}
@@ -0,0 +1,371 @@
+//! `commaslash cache` subcommands.
+
+use crate::cache;
+use crate::cache::{commaslash_dirs, CacheEntry};
+use crate::digest::Digest;
+use crate::gc::remove_entry;
+use crate::gen::GenOptions;
+use crate::install::{install_in, is_installed_exe, InstallOptions};
+use crate::manifest;
+use crate::script::find_scripts;
+use crate::spec::resolve::ResolvedTargetSpec;
+use crate::target_platform::TargetPlatform;
+use anyhow::Context;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Inspect the cache of installed binaries.
+#[derive(clap::Args)]
+pub(crate) struct CacheArgs {
+    #[clap(subcommand)]
+    command: CacheCommand,
+}
+
+#[derive(clap::Subcommand)]
+enum CacheCommand {
+    /// List cache entries.
+    List(ListArgs),
+    /// Check stored archives and installs for corruption, reinstalling corrupt installs.
+    Verify(VerifyArgs),
+    /// Remove cache entries.
+    Rm(RmArgs),
+}
+
+#[derive(clap::Args)]
+struct RootArgs {
+    /// Find urls and names of entries in scripts under this directory.
+    /// Can be specified multiple times.
+    #[clap(long, value_name = "dir")]
+    root: Vec<PathBuf>,
+}
+
+#[derive(clap::Args)]
+struct ListArgs {
+    #[clap(flatten)]
+    root: RootArgs,
+}
+
+#[derive(clap::Args)]
+struct VerifyArgs {
+    #[clap(flatten)]
+    root: RootArgs,
+}
+
+#[derive(clap::Args)]
+struct RmArgs {
+    #[clap(flatten)]
+    root: RootArgs,
+    /// Only print what would be removed.
+    #[clap(long)]
+    dry_run: bool,
+    /// Digest of the entry as `<algorithm>:<hex>` or as in the cache dir name,
+    /// or name of a script or a binary found under `--root`.
+    #[clap(value_name = "digest|name", required = true)]
+    entries: Vec<String>,
+}
+
+/// Where a cache entry comes from.
+struct Source {
+    script: PathBuf,
+    target_platform: TargetPlatform,
+    spec: ResolvedTargetSpec,
+}
+
+impl Source {
+    fn has_name(&self, name: &str) -> bool {
+        self.script.file_name() == Some(name.as_ref())
+            || Path::new(self.spec.path.as_str()).file_name() == Some(name.as_ref())
+    }
+}
+
+/// Sources of entries found in scripts under roots.
+struct Sources {
+    by_digest: HashMap<Digest, Vec<Source>>,
+    /// Options of the scripts, which determine their cache dirs.
+    options: Vec<GenOptions>,
+}
+
+impl Sources {
+    fn find(roots: &RootArgs) -> anyhow::Result<Sources> {
+        let mut by_digest: HashMap<Digest, Vec<Source>> = HashMap::new();
+        let mut options = Vec::new();
+        for root in &roots.root {
+            for (script, parsed) in find_scripts(root)?.scripts {
+                for (target_platform, spec) in parsed.spec.specs {
+                    by_digest.entry(spec.digest).or_default().push(Source {
+                        script: script.clone(),
+                        target_platform,
+                        spec,
+                    });
+                }
+                options.push(parsed.options);
+            }
+        }
+        Ok(Sources { by_digest, options })
+    }
+
+    fn get(&self, digest: Digest) -> &[Source] {
+        self.by_digest
+            .get(&digest)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+}
+
+pub(crate) fn cache_main(args: &CacheArgs) -> anyhow::Result<()> {
+    let target_platform = TargetPlatform::current()?;
+    let root = match &args.command {
+        CacheCommand::List(args) => &args.root,
+        CacheCommand::Verify(args) => &args.root,
+        CacheCommand::Rm(args) => &args.root,
+    };
+    let sources = Sources::find(root)?;
+    // Scripts generated with `--cache-dir-expr` install elsewhere.
+    let dirs = commaslash_dirs(&target_platform.os(), &sources.options)?;
+    match &args.command {
+        CacheCommand::List(_) => list(&dirs, &sources),
+        CacheCommand::Verify(_) => verify(&dirs, &sources),
+        CacheCommand::Rm(args) => rm(&dirs, &sources, args),
+    }
+}
+
+fn all_entries(dirs: &[PathBuf]) -> anyhow::Result<Vec<CacheEntry>> {
+    let mut entries = Vec::new();
+    for commaslash_dir in dirs {
+        entries.extend(cache::list_entries(commaslash_dir)?);
+    }
+    Ok(entries)
+}
+
+fn list(dirs: &[PathBuf], sources: &Sources) -> anyhow::Result<()> {
+    let now = SystemTime::now();
+    for entry in all_entries(dirs)? {
+        println!("{}", entry.summary(now));
+        let archive_path = entry.archive_path();
+        if archive_path.exists() {
+            println!("  archive {}", archive_path.display());
+        }
+        for source in sources.get(entry.digest) {
+            println!(
+                "  {} {} in {}",
+                source.target_platform,
+                source.spec.url,
+                source.script.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+fn verify(dirs: &[PathBuf], sources: &Sources) -> anyhow::Result<()> {
+    let now = SystemTime::now();
+    let install_options = InstallOptions {
+        verify: true,
+        ..InstallOptions::from_env()
+    };
+    let mut corrupt = 0;
+    for entry in all_entries(dirs)? {
+        println!("{}", entry.summary(now));
+        if let Some(error) = verify_archive(&entry)? {
+            println!("  {error}");
+            // Stored archives are only a cache, so a corrupt one is simply dropped.
+            println!("  remove {}", entry.archive_path().display());
+            fs::remove_file(entry.archive_path())?;
+        }
+        let errors = verify_install(&entry, sources)?;
+        for error in &errors {
+            println!("  {error}");
+        }
+        if errors.is_empty() {
+            continue;
+        }
+        match sources.get(entry.digest).first() {
+            Some(source) => {
+                install_in(&entry.commaslash_dir, &install_options, &source.spec)
+                    .with_context(|| format!("Failed to reinstall `{}`", source.spec.url))?;
+                println!("  reinstalled from {}", source.spec.url);
+            }
+            None => corrupt += 1,
+        }
+    }
+    if corrupt != 0 {
+        return Err(anyhow::anyhow!(
+            "{corrupt} corrupt entries found; specify `--root` with scripts to reinstall them, \
+            or remove them with `commaslash cache rm`"
+        ));
+    }
+    Ok(())
+}
+
+/// Stored archive does not match its digest.
+fn verify_archive(entry: &CacheEntry) -> anyhow::Result<Option<String>> {
+    let archive_path = entry.archive_path();
+    if !archive_path.exists() {
+        return Ok(None);
+    }
+    let digest = Digest::of_file(entry.digest.algorithm(), &archive_path)?;
+    if digest == entry.digest {
+        return Ok(None);
+    }
+    Ok(Some(format!(
+        "archive {} has digest {digest}",
+        archive_path.display()
+    )))
+}
+
+/// Problems found with the install.
+fn verify_install(entry: &CacheEntry, sources: &Sources) -> anyhow::Result<Vec<String>> {
+    let mut errors = Vec::new();
+    let install_dir = entry.install_dir();
+    if !install_dir.exists() {
+        return Ok(errors);
+    }
+    if !manifest::verify(&install_dir, &entry.manifest_path())? {
+        errors.push(format!(
+            "{} does not match {}",
+            install_dir.display(),
+            entry.manifest_path().display()
+        ));
+    }
+    let specs: BTreeMap<&str, &ResolvedTargetSpec> = sources
+        .get(entry.digest)
+        .iter()
+        .map(|source| (source.spec.path.as_str(), &source.spec))
+        .collect();
+    for (path, spec) in specs {
+        let exe = install_dir.join(path);
+        if !is_installed_exe(spec, &exe) {
+            errors.push(format!("{} is not executable", exe.display()));
+        }
+    }
+    Ok(errors)
+}
+
+fn rm(dirs: &[PathBuf], sources: &Sources, args: &RmArgs) -> anyhow::Result<()> {
+    let entries = all_entries(dirs)?;
+    let mut selected = Vec::new();
+    for name in &args.entries {
+        let digest = Digest::parse(name)
+            .ok()
+            .or_else(|| Digest::from_cache_name(name));
+        let digests: Vec<Digest> = match digest {
+            Some(digest) => vec![digest],
+            None => sources
+                .by_digest
+                .iter()
+                .filter(|(_, sources)| sources.iter().any(|source| source.has_name(name)))
+                .map(|(digest, _)| *digest)
+                .collect(),
+        };
+        let matched: Vec<&CacheEntry> = entries
+            .iter()
+            .filter(|entry| digests.contains(&entry.digest))
+            .collect();
+        if matched.is_empty() {
+            return Err(anyhow::anyhow!(
+                "No cache entry matches `{name}`; names are resolved using scripts under `--root`"
+            ));
+        }
+        selected.extend(matched);
+    }
+    for entry in selected {
+        remove_entry(entry, args.dry_run)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cache::commaslash_dirs;
+    use crate::cache_cmd::{
+        all_entries, rm, verify, verify_archive, verify_install, RmArgs, RootArgs, Sources,
+    };
+    use crate::gen::GenOptions;
+    use crate::install::{install, InstallOptions};
+    use crate::script::Script;
+    use crate::target_platform::TargetPlatform;
+    use crate::testutil::{test_cache_dirs, write_test_script};
+    use std::fs;
+    use std::path::Path;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_verify_and_rm() {
+        let dir = TempDir::new().unwrap();
+        let scripts = dir.path().join("scripts");
+        let dirs = test_cache_dirs(dir.path(), "cache");
+        let script = write_test_script(
+            &scripts,
+            &dir.path().join("archive"),
+            "bin/tool",
+            &GenOptions::default(),
+        );
+        let spec = Script::read(&script).unwrap().spec;
+        let spec = spec.specs.get(&TargetPlatform::current().unwrap()).unwrap();
+        let options = InstallOptions {
+            seed_dir: None,
+            offline: false,
+            keep_archives: true,
+            verify: false,
+        };
+        install(&dirs, &options, spec).unwrap();
+        let all_dirs = dirs.all().map(Path::to_owned);
+
+        let roots = RootArgs {
+            root: vec![scripts],
+        };
+        let sources = Sources::find(&roots).unwrap();
+        let entries = all_entries(&all_dirs).unwrap();
+        assert_eq!(1, entries.len());
+        let entry = &entries[0];
+        assert_eq!(spec.digest, entry.digest);
+        assert!(verify_archive(entry).unwrap().is_none());
+        assert!(verify_install(entry, &sources).unwrap().is_empty());
+
+        fs::write(entry.archive_path(), "corrupt").unwrap();
+        fs::remove_file(entry.install_dir().join("bin/tool")).unwrap();
+        assert!(verify_archive(entry).unwrap().is_some());
+        assert_eq!(2, verify_install(entry, &sources).unwrap().len());
+
+        // Without scripts the install cannot be repaired.
+        let no_sources = Sources::find(&RootArgs { root: Vec::new() }).unwrap();
+        assert!(verify(&all_dirs, &no_sources).is_err());
+        assert!(!entry.archive_path().exists());
+
+        verify(&all_dirs, &sources).unwrap();
+        assert!(verify_install(entry, &sources).unwrap().is_empty());
+
+        let rm_args = |name: &str| RmArgs {
+            root: RootArgs { root: Vec::new() },
+            dry_run: false,
+            entries: vec![name.to_owned()],
+        };
+        assert!(rm(&all_dirs, &sources, &rm_args("nope")).is_err());
+        rm(&all_dirs, &sources, &rm_args("tool")).unwrap();
+        assert!(all_entries(&all_dirs).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_script_cache_dirs() {
+        let dir = TempDir::new().unwrap();
+        let scripts = dir.path().join("scripts");
+        let options = GenOptions {
+            cache_dir_expr: Some(format!("{}/custom", dir.path().display())),
+            ..GenOptions::default()
+        };
+        write_test_script(&scripts, &dir.path().join("archive"), "bin/tool", &options);
+
+        let sources = Sources::find(&RootArgs {
+            root: vec![scripts],
+        })
+        .unwrap();
+        let os = TargetPlatform::current().unwrap().os();
+        let dirs = commaslash_dirs(&os, &sources.options).unwrap();
+        assert!(dirs.contains(&dir.path().join("custom/commaslash")));
+        // Default dirs are always included and not repeated.
+        assert_eq!(dirs[..2], commaslash_dirs(&os, &[]).unwrap());
+        assert_eq!(3, dirs.len());
+    }
+}
@@ -0,0 +1,109 @@
+//! Checksum files published next to release assets, like `SHA256SUMS`.
+//!
+//! Lines are `<sha256>  <name>` as printed by `sha256sum`,
+//! or `<sha256> *<name>` for binary mode.
+
+use crate::digest::{Digest, DigestAlgorithm};
+use crate::download::download;
+use anyhow::Context;
+
+/// Download the checksum file and find the sha256 of the asset at `url`.
+pub(crate) fn resolve_checksum(checksums_url: &str, url: &str) -> anyhow::Result<Digest> {
+    let content = download(checksums_url)?;
+    let content = String::from_utf8(content).context("Checksum file is not UTF-8")?;
+    let file_name = asset_file_name(url)?;
+    find_checksum(&content, file_name)
+        .with_context(|| format!("Failed to find checksum in `{checksums_url}`"))
+}
+
+fn asset_file_name(url: &str) -> anyhow::Result<&str> {
+    match url.rsplit_once('/') {
+        Some((_, file_name)) if !file_name.is_empty() => Ok(file_name),
+        _ => Err(anyhow::anyhow!("No file name in url `{url}`")),
+    }
+}
+
+/// Find the digest of `file_name` in the checksum file content.
+///
+/// Names listed with a directory, like `./dist/<file_name>`, match too.
+fn find_checksum(content: &str, file_name: &str) -> anyhow::Result<Digest> {
+    let mut found = None;
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim_end();
+        if line.is_empty() {
+            continue;
+        }
+        let Some((hex, name)) = line.split_once(' ') else {
+            return Err(anyhow::anyhow!("Malformed line {}: `{line}`", i + 1));
+        };
+        let name = name.strip_prefix([' ', '*']).unwrap_or(name);
+        if name != file_name && !name.ends_with(&format!("/{file_name}")) {
+            continue;
+        }
+        if found.is_some() {
+            return Err(anyhow::anyhow!("`{file_name}` is listed more than once"));
+        }
+        let digest = Digest::from_hex(DigestAlgorithm::Sha256, hex)
+            .with_context(|| format!("Malformed line {}: `{line}`", i + 1))?;
+        found = Some(digest);
+    }
+    found.with_context(|| format!("`{file_name}` is not listed"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::checksums::find_checksum;
+    use crate::digest::{Digest, DigestAlgorithm};
+    use crate::spec::resolve::{ResolveOptions, ResolvedTargetSpec};
+    use crate::target_platform::TargetPlatform;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_find_checksum() {
+        let a = Digest::of_bytes(DigestAlgorithm::Sha256, b"a");
+        let b = Digest::of_bytes(DigestAlgorithm::Sha256, b"b");
+        let content = format!(
+            "{}  tool-linux.tar.gz\n{} *./dist/tool-macos.zip\n",
+            a.hex(),
+            b.hex()
+        );
+        assert_eq!(a, find_checksum(&content, "tool-linux.tar.gz").unwrap());
+        assert_eq!(b, find_checksum(&content, "tool-macos.zip").unwrap());
+        assert!(find_checksum(&content, "linux.tar.gz").is_err());
+        assert!(find_checksum(&content, "tool-windows.zip").is_err());
+
+        let twice = format!("{content}{}  tool-linux.tar.gz\n", b.hex());
+        assert!(find_checksum(&twice, "tool-linux.tar.gz").is_err());
+    }
+
+    #[test]
+    fn test_resolve_checksums_url() {
+        let dir = TempDir::new().unwrap();
+        let sha256 = Digest::of_bytes(DigestAlgorithm::Sha256, b"archive");
+        let sums = dir.path().join("SHA256SUMS");
+        fs::write(&sums, format!("{}  tool.zip\n", sha256.hex())).unwrap();
+
+        let spec = ResolvedTargetSpec::parse_and_resolve(
+            &format!(
+                "url=https://example.com/v1/tool.zip size=7 path=tool checksums_url=file://{}",
+                sums.display()
+            ),
+            TargetPlatform::LinuxX86_64,
+            &ResolveOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(sha256, spec.digest);
+        assert!(!spec.to_string().contains("checksums_url"));
+
+        assert!(ResolvedTargetSpec::parse_and_resolve(
+            &format!(
+                "url=https://example.com/v1/other.zip size=7 path=tool checksums_url=file://{}",
+                sums.display()
+            ),
+            TargetPlatform::LinuxX86_64,
+            &ResolveOptions::default(),
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,189 @@
+use anyhow::Context;
+use ordinal_map::Ordinal;
+use sha2::{Sha256, Sha512};
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+
+#[derive(
+    ordinal_map::Ordinal,
+    derive_more::Display,
+    Copy,
+    Clone,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Debug,
+)]
+pub(crate) enum DigestAlgorithm {
+    #[display("sha256")]
+    Sha256,
+    #[display("sha512")]
+    Sha512,
+    #[display("blake3")]
+    Blake3,
+}
+
+impl DigestAlgorithm {
+    /// Inverse of `Display`, also the spec key.
+    pub(crate) fn from_name(name: &str) -> Option<DigestAlgorithm> {
+        DigestAlgorithm::all_values().find(|a| a.to_string() == name)
+    }
+
+    fn len(self) -> usize {
+        match self {
+            DigestAlgorithm::Sha256 => 32,
+            DigestAlgorithm::Sha512 => 64,
+            DigestAlgorithm::Blake3 => 32,
+        }
+    }
+}
+
+/// Digest tagged with its algorithm, displayed as `<algorithm>:<hex>`.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub(crate) struct Digest {
+    algorithm: DigestAlgorithm,
+    /// Only the first `algorithm.len()` bytes are used, the rest are zero.
+    bytes: [u8; 64],
+}
+
+impl Display for Digest {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.algorithm, self.hex())
+    }
+}
+
+impl Digest {
+    fn new(algorithm: DigestAlgorithm, digest: &[u8]) -> Digest {
+        assert_eq!(algorithm.len(), digest.len());
+        let mut bytes = [0; 64];
+        bytes[..digest.len()].copy_from_slice(digest);
+        Digest { algorithm, bytes }
+    }
+
+    pub(crate) fn from_hex(algorithm: DigestAlgorithm, hex: &str) -> anyhow::Result<Digest> {
+        let bytes = hex::decode(hex)
+            .with_context(|| format!("Failed to parse {algorithm} from hex string"))?;
+        if bytes.len() != algorithm.len() {
+            return Err(anyhow::anyhow!("Incorrect {algorithm} length"));
+        }
+        Ok(Digest::new(algorithm, &bytes))
+    }
+
+    /// Inverse of `Display`.
+    pub(crate) fn parse(digest: &str) -> anyhow::Result<Digest> {
+        let (algorithm, hex) = digest
+            .split_once(':')
+            .with_context(|| format!("Digest must be `<algorithm>:<hex>`, got `{digest}`"))?;
+        let algorithm = DigestAlgorithm::from_name(algorithm)
+            .with_context(|| format!("Unknown digest algorithm: `{algorithm}`"))?;
+        Digest::from_hex(algorithm, hex)
+    }
+
+    pub(crate) fn algorithm(&self) -> DigestAlgorithm {
+        self.algorithm
+    }
+
+    pub(crate) fn hex(&self) -> String {
+        hex::encode(&self.bytes[..self.algorithm.len()])
+    }
+
+    /// Name of install dirs and stored archives.
+    ///
+    /// Plain hex for sha256, so caches made before other algorithms were supported stay valid,
+    /// and `<algorithm>-<hex>` for others, so blake3 and sha256 digests never collide.
+    pub(crate) fn cache_name(&self) -> String {
+        match self.algorithm {
+            DigestAlgorithm::Sha256 => self.hex(),
+            algorithm => format!("{algorithm}-{}", self.hex()),
+        }
+    }
+
+    /// Inverse of `cache_name`.
+    pub(crate) fn from_cache_name(name: &str) -> Option<Digest> {
+        match name.split_once('-') {
+            Some((algorithm, hex)) => {
+                let algorithm = DigestAlgorithm::from_name(algorithm)?;
+                if algorithm == DigestAlgorithm::Sha256 {
+                    return None;
+                }
+                Digest::from_hex(algorithm, hex).ok()
+            }
+            None => Digest::from_hex(DigestAlgorithm::Sha256, name).ok(),
+        }
+    }
+
+    pub(crate) fn of_bytes(algorithm: DigestAlgorithm, bytes: &[u8]) -> Digest {
+        Digest::of_reader(algorithm, &mut &bytes[..]).unwrap()
+    }
+
+    pub(crate) fn of_file(algorithm: DigestAlgorithm, path: &Path) -> anyhow::Result<Digest> {
+        let mut file =
+            File::open(path).with_context(|| format!("Failed to open `{}`", path.display()))?;
+        Digest::of_reader(algorithm, &mut file)
+            .with_context(|| format!("Failed to read `{}`", path.display()))
+    }
+
+    fn of_reader(algorithm: DigestAlgorithm, reader: &mut impl Read) -> io::Result<Digest> {
+        Ok(match algorithm {
+            DigestAlgorithm::Sha256 => {
+                let mut hasher = <Sha256 as sha2::Digest>::new();
+                io::copy(reader, &mut hasher)?;
+                Digest::new(algorithm, &sha2::Digest::finalize(hasher))
+            }
+            DigestAlgorithm::Sha512 => {
+                let mut hasher = <Sha512 as sha2::Digest>::new();
+                io::copy(reader, &mut hasher)?;
+                Digest::new(algorithm, &sha2::Digest::finalize(hasher))
+            }
+            DigestAlgorithm::Blake3 => {
+                let mut hasher = blake3::Hasher::new();
+                io::copy(reader, &mut hasher)?;
+                Digest::new(algorithm, hasher.finalize().as_bytes())
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::digest::{Digest, DigestAlgorithm};
+    use ordinal_map::Ordinal;
+
+    #[test]
+    fn test_of_bytes() {
+        assert_eq!(
+            "sha256:2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824",
+            Digest::of_bytes(DigestAlgorithm::Sha256, b"hello").to_string()
+        );
+        assert_eq!(
+            "blake3:ea8f163db38682925e4491c5e58d4bb3506ef8c14eb78a86e908c5624a67200f",
+            Digest::of_bytes(DigestAlgorithm::Blake3, b"hello").to_string()
+        );
+        assert_eq!(
+            "sha512:9b71d224bd62f3785d96d46ad3ea3d73319bfbc2890caadae2dff72519673ca7\
+            2323c3d99ba5c11d7c7acc6e14b8c5da0c4663475c2e5c3adef46f73bcdec043",
+            Digest::of_bytes(DigestAlgorithm::Sha512, b"hello").to_string()
+        );
+    }
+
+    #[test]
+    fn test_cache_name() {
+        for algorithm in DigestAlgorithm::all_values() {
+            let digest = Digest::of_bytes(algorithm, b"hello");
+            assert_eq!(Some(digest), Digest::from_cache_name(&digest.cache_name()));
+            assert_eq!(digest, Digest::parse(&digest.to_string()).unwrap());
+        }
+        let sha256 = Digest::of_bytes(DigestAlgorithm::Sha256, b"hello");
+        assert_eq!(sha256.hex(), sha256.cache_name());
+        assert_eq!(
+            None,
+            Digest::from_cache_name(&format!("sha256-{}", sha256.hex()))
+        );
+        assert_eq!(None, Digest::from_cache_name("blake3-00"));
+    }
+}
@@ -0,0 +1,90 @@
+use crate::digest::{Digest, DigestAlgorithm};
+
+#[derive(ordinal_map::Ordinal)]
+pub(crate) enum DigestCommand {
+    Sha256sum,
+    Sha512sum,
+    Shasum256,
+    Shasum512,
+    B3sum,
+}
+
+impl DigestCommand {
+    pub(crate) fn command(&self) -> &'static str {
+        match self {
+            DigestCommand::Sha256sum => "sha256sum",
+            DigestCommand::Sha512sum => "sha512sum",
+            DigestCommand::Shasum256 | DigestCommand::Shasum512 => "shasum",
+            DigestCommand::B3sum => "b3sum",
+        }
+    }
+
+    pub(crate) fn algorithm(&self) -> DigestAlgorithm {
+        match self {
+            DigestCommand::Sha256sum | DigestCommand::Shasum256 => DigestAlgorithm::Sha256,
+            DigestCommand::Sha512sum | DigestCommand::Shasum512 => DigestAlgorithm::Sha512,
+            DigestCommand::B3sum => DigestAlgorithm::Blake3,
+        }
+    }
+
+    pub(crate) fn test_command(&self) -> String {
+        format!("{} --version", self.command())
+    }
+
+    /// Command which fails unless file has the digest, which must be of `self.algorithm()`.
+    pub(crate) fn check_command(&self, digest: Digest, file_path: &str) -> String {
+        assert_eq!(self.algorithm(), digest.algorithm());
+        let hex = digest.hex();
+        let command = match self {
+            DigestCommand::Sha256sum => "sha256sum",
+            DigestCommand::Sha512sum => "sha512sum",
+            DigestCommand::Shasum256 => "shasum -a 256",
+            DigestCommand::Shasum512 => "shasum -a 512",
+            DigestCommand::B3sum => "b3sum",
+        };
+        format!("echo \"{hex}  {file_path}\" | {command} --check -")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::digest::Digest;
+    use crate::digest_command::DigestCommand;
+    use crate::testutil::{assert_shell_err, assert_shell_ok};
+    use ordinal_map::Ordinal;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn commands_available() -> Vec<DigestCommand> {
+        let mut commands_available = Vec::new();
+        for command in DigestCommand::all_values() {
+            if which::which(command.command()).is_ok() {
+                commands_available.push(command);
+            }
+        }
+        assert!(!commands_available.is_empty());
+        commands_available
+    }
+
+    #[test]
+    fn test_test_command() {
+        for command in commands_available() {
+            assert_shell_ok(command.test_command())
+        }
+    }
+
+    #[test]
+    fn test_check_command() {
+        let tempfile = TempDir::new().unwrap();
+
+        let path = tempfile.path().join("hello.txt");
+        fs::write(&path, "hello").unwrap();
+
+        for command in commands_available() {
+            let expected = Digest::of_bytes(command.algorithm(), b"hello");
+            let wrong = Digest::of_bytes(command.algorithm(), b"wrong");
+            assert_shell_ok(command.check_command(expected, path.to_str().unwrap()));
+            assert_shell_err(command.check_command(wrong, path.to_str().unwrap()));
+        }
+    }
+}
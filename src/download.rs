@@ -0,0 +1,23 @@
+//! Downloads made by commaslash itself rather than by generated scripts.
+
+use anyhow::Context;
+use std::process::Command;
+
+/// Download `url` into memory with `curl`, using the same flags as generated scripts.
+pub(crate) fn download(url: &str) -> anyhow::Result<Vec<u8>> {
+    let mut command = Command::new("curl");
+    command
+        .args(["--globoff", "--location", "--retry", "3", "--fail"])
+        .args(["--silent", "--show-error"])
+        .arg(url);
+    let output = command
+        .output()
+        .with_context(|| format!("Failed to run {command:?}"))?;
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(
+            "Failed to download `{url}`: {}",
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        ));
+    }
+    Ok(output.stdout)
+}
@@ -0,0 +1,245 @@
+//! Check ELF and Mach-O headers of binaries against the target platform,
+//! to catch a binary for one platform listed under another.
+
+use crate::os::Os;
+use crate::target_platform::TargetPlatform;
+
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+const ELFCLASS64: u8 = 2;
+const ELFDATA2MSB: u8 = 2;
+const EM_X86_64: u16 = 62;
+const EM_AARCH64: u16 = 183;
+
+const MH_MAGIC: u32 = 0xfeedface;
+const MH_MAGIC_64: u32 = 0xfeedfacf;
+const FAT_MAGIC: u32 = 0xcafebabe;
+const FAT_MAGIC_64: u32 = 0xcafebabf;
+const CPU_TYPE_X86_64: u32 = 0x0100_0007;
+const CPU_TYPE_ARM64: u32 = 0x0100_000c;
+/// Java class files share `FAT_MAGIC`; their version makes the arch count large.
+const MAX_FAT_ARCHS: u32 = 30;
+
+/// Enough for fat headers with `MAX_FAT_ARCHS` arches.
+pub(crate) const HEADER_SIZE: usize = 4096;
+
+enum ExeFormat {
+    Elf {
+        class64: bool,
+        machine: u16,
+    },
+    MachO {
+        cpu_types: Vec<u32>,
+    },
+    /// Scripts and anything else, which are not checked.
+    Other,
+}
+
+fn truncated() -> anyhow::Error {
+    anyhow::anyhow!("Truncated executable header")
+}
+
+fn u16_at(header: &[u8], offset: usize, big_endian: bool) -> anyhow::Result<u16> {
+    let bytes = header.get(offset..offset + 2).ok_or_else(truncated)?;
+    let bytes = bytes.try_into().unwrap();
+    Ok(match big_endian {
+        true => u16::from_be_bytes(bytes),
+        false => u16::from_le_bytes(bytes),
+    })
+}
+
+fn u32_at(header: &[u8], offset: usize, big_endian: bool) -> anyhow::Result<u32> {
+    let bytes = header.get(offset..offset + 4).ok_or_else(truncated)?;
+    let bytes = bytes.try_into().unwrap();
+    Ok(match big_endian {
+        true => u32::from_be_bytes(bytes),
+        false => u32::from_le_bytes(bytes),
+    })
+}
+
+impl ExeFormat {
+    fn parse(header: &[u8]) -> anyhow::Result<ExeFormat> {
+        if header.starts_with(ELF_MAGIC) {
+            let class = *header.get(4).ok_or_else(truncated)?;
+            let data = *header.get(5).ok_or_else(truncated)?;
+            return Ok(ExeFormat::Elf {
+                class64: class == ELFCLASS64,
+                machine: u16_at(header, 18, data == ELFDATA2MSB)?,
+            });
+        }
+        let Ok(magic) = u32_at(header, 0, true) else {
+            return Ok(ExeFormat::Other);
+        };
+        if magic == FAT_MAGIC || magic == FAT_MAGIC_64 {
+            let count = u32_at(header, 4, true)?;
+            if count == 0 || count > MAX_FAT_ARCHS {
+                return Ok(ExeFormat::Other);
+            }
+            let arch_size = if magic == FAT_MAGIC { 20 } else { 32 };
+            let cpu_types = (0..count as usize)
+                .map(|i| u32_at(header, 8 + i * arch_size, true))
+                .collect::<anyhow::Result<_>>()?;
+            return Ok(ExeFormat::MachO { cpu_types });
+        }
+        for big_endian in [false, true] {
+            let magic = u32_at(header, 0, big_endian)?;
+            if magic == MH_MAGIC || magic == MH_MAGIC_64 {
+                return Ok(ExeFormat::MachO {
+                    cpu_types: vec![u32_at(header, 4, big_endian)?],
+                });
+            }
+        }
+        Ok(ExeFormat::Other)
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            ExeFormat::Elf { class64, machine } => {
+                let bits = if *class64 { 64 } else { 32 };
+                let machine = match *machine {
+                    EM_X86_64 => "x86_64".to_owned(),
+                    EM_AARCH64 => "aarch64".to_owned(),
+                    3 => "i386".to_owned(),
+                    40 => "arm".to_owned(),
+                    machine => format!("machine {machine}"),
+                };
+                format!("a {bits}-bit ELF binary for {machine}")
+            }
+            ExeFormat::MachO { cpu_types } => {
+                let cpu_types: Vec<String> = cpu_types
+                    .iter()
+                    .map(|&cpu_type| match cpu_type {
+                        CPU_TYPE_X86_64 => "x86_64".to_owned(),
+                        CPU_TYPE_ARM64 => "arm64".to_owned(),
+                        7 => "i386".to_owned(),
+                        12 => "arm".to_owned(),
+                        18 => "ppc".to_owned(),
+                        cpu_type => format!("cpu type {cpu_type:#x}"),
+                    })
+                    .collect();
+                format!("a Mach-O binary for {}", cpu_types.join(", "))
+            }
+            ExeFormat::Other => "not a binary".to_owned(),
+        }
+    }
+}
+
+/// Fail if `header`, the first bytes of an executable, is an ELF or Mach-O binary
+/// which does not run on `target_platform`.
+pub(crate) fn check_exe_header(
+    header: &[u8],
+    target_platform: TargetPlatform,
+) -> anyhow::Result<()> {
+    let format = ExeFormat::parse(header)?;
+    let ok = match (&format, target_platform.os()) {
+        (ExeFormat::Other, _) => true,
+        (ExeFormat::Elf { class64, machine }, Os::Linux) => {
+            let expected = match target_platform {
+                TargetPlatform::LinuxX86_64 => EM_X86_64,
+                TargetPlatform::MacosX86_64 | TargetPlatform::MacosAarch64 => unreachable!(),
+            };
+            *class64 && *machine == expected
+        }
+        (ExeFormat::MachO { cpu_types }, Os::Macos) => {
+            let expected = match target_platform {
+                TargetPlatform::MacosX86_64 => CPU_TYPE_X86_64,
+                TargetPlatform::MacosAarch64 => CPU_TYPE_ARM64,
+                TargetPlatform::LinuxX86_64 => unreachable!(),
+            };
+            cpu_types.contains(&expected)
+        }
+        (ExeFormat::Elf { .. }, Os::Macos) | (ExeFormat::MachO { .. }, Os::Linux) => false,
+    };
+    if !ok {
+        return Err(anyhow::anyhow!(
+            "Executable is {}, which does not run on {target_platform}",
+            format.describe()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::exe_header::{check_exe_header, HEADER_SIZE};
+    use crate::target_platform::TargetPlatform;
+    use std::fs::File;
+    use std::io::Read;
+
+    fn elf(class: u8, machine: u16) -> Vec<u8> {
+        let mut header = b"\x7fELF".to_vec();
+        header.extend([class, 1, 1, 0]);
+        header.resize(18, 0);
+        header.extend(machine.to_le_bytes());
+        header.resize(64, 0);
+        header
+    }
+
+    fn macho(cpu_type: u32) -> Vec<u8> {
+        let mut header = 0xfeedfacf_u32.to_le_bytes().to_vec();
+        header.extend(cpu_type.to_le_bytes());
+        header.resize(32, 0);
+        header
+    }
+
+    fn fat(cpu_types: &[u32]) -> Vec<u8> {
+        let mut header = 0xcafebabe_u32.to_be_bytes().to_vec();
+        header.extend((cpu_types.len() as u32).to_be_bytes());
+        for cpu_type in cpu_types {
+            header.extend(cpu_type.to_be_bytes());
+            header.extend([0; 16]);
+        }
+        header
+    }
+
+    #[test]
+    fn test_check_exe_header() {
+        use TargetPlatform::*;
+
+        let linux_x86_64 = elf(2, 62);
+        let linux_aarch64 = elf(2, 183);
+        let linux_i386 = elf(1, 3);
+        let macos_x86_64 = macho(0x0100_0007);
+        let macos_arm64 = macho(0x0100_000c);
+        let universal = fat(&[0x0100_0007, 0x0100_000c]);
+        // Java 8 class file.
+        let class_file = b"\xca\xfe\xba\xbe\x00\x00\x00\x34".to_vec();
+        let script = b"#!/bin/sh\n".to_vec();
+
+        for (header, ok) in [
+            (&linux_x86_64, [LinuxX86_64].as_slice()),
+            (&linux_aarch64, &[]),
+            (&linux_i386, &[]),
+            (&macos_x86_64, &[MacosX86_64]),
+            (&macos_arm64, &[MacosAarch64]),
+            (&universal, &[MacosX86_64, MacosAarch64]),
+            (&class_file, &[LinuxX86_64, MacosX86_64, MacosAarch64]),
+            (&script, &[LinuxX86_64, MacosX86_64, MacosAarch64]),
+        ] {
+            for target_platform in [LinuxX86_64, MacosX86_64, MacosAarch64] {
+                assert_eq!(
+                    ok.contains(&target_platform),
+                    check_exe_header(header, target_platform).is_ok(),
+                    "{target_platform}"
+                );
+            }
+        }
+
+        let error = check_exe_header(&macos_arm64, MacosX86_64).unwrap_err();
+        assert_eq!(
+            "Executable is a Mach-O binary for arm64, which does not run on macos-x86_64",
+            error.to_string()
+        );
+        assert!(check_exe_header(&linux_x86_64[..10], LinuxX86_64).is_err());
+    }
+
+    #[test]
+    fn test_current_exe() {
+        let mut header = Vec::new();
+        File::open(std::env::current_exe().unwrap())
+            .unwrap()
+            .take(HEADER_SIZE as u64)
+            .read_to_end(&mut header)
+            .unwrap();
+        check_exe_header(&header, TargetPlatform::current().unwrap()).unwrap();
+    }
+}
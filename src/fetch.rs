@@ -0,0 +1,356 @@
+use crate::cache::CacheDirs;
+use crate::install::{install, InstallOptions};
+use crate::script::Script;
+use crate::target_platform::TargetPlatform;
+use anyhow::Context;
+use std::path::{Path, PathBuf};
+
+/// Download and install binaries for generated scripts without running them.
+#[derive(clap::Args)]
+pub(crate) struct FetchArgs {
+    /// Scripts generated by commaslash.
+    #[clap(value_name = "script", required = true)]
+    scripts: Vec<String>,
+}
+
+pub(crate) fn fetch(args: &FetchArgs) -> anyhow::Result<()> {
+    let target_platform = TargetPlatform::current()?;
+    for script in &args.scripts {
+        let exe = fetch_script(
+            Path::new(script),
+            target_platform,
+            None,
+            &InstallOptions::from_env(),
+        )
+        .with_context(|| format!("Failed to fetch `{script}`"))?;
+        println!("{}", exe.display());
+    }
+    Ok(())
+}
+
+/// `dirs` overrides cache dirs from environment.
+fn fetch_script(
+    script: &Path,
+    target_platform: TargetPlatform,
+    dirs: Option<&CacheDirs>,
+    install_options: &InstallOptions,
+) -> anyhow::Result<PathBuf> {
+    let Script { spec, options } = Script::read(script)?;
+    let spec = spec
+        .specs
+        .get(&target_platform)
+        .with_context(|| format!("No spec for {target_platform}"))?;
+    let dirs = match dirs {
+        Some(dirs) => dirs,
+        None => &CacheDirs::from_env(&target_platform.os(), &options)?,
+    };
+    install(dirs, install_options, spec)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::digest::{Digest, DigestAlgorithm};
+    use crate::fetch::fetch_script;
+    use crate::gen::GenOptions;
+    use crate::install::InstallOptions;
+    use crate::target_platform::TargetPlatform;
+    use crate::testutil::{
+        run_script, script_command, test_cache_dirs, write_spec_script, write_tar,
+        write_test_script, write_zip,
+    };
+    use std::fs;
+    use std::io::Write;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
+    use tempfile::TempDir;
+    use zip::write::SimpleFileOptions;
+
+    fn install_options() -> InstallOptions {
+        InstallOptions {
+            seed_dir: None,
+            offline: false,
+            keep_archives: false,
+            verify: false,
+        }
+    }
+
+    #[test]
+    fn test_fetch_script() {
+        let dir = TempDir::new().unwrap();
+        let archive_dir = dir.path().join("archive");
+        let script =
+            write_test_script(dir.path(), &archive_dir, "bin/tool", &GenOptions::default());
+        let dirs = test_cache_dirs(dir.path(), "cache");
+
+        let exe = fetch_script(
+            &script,
+            TargetPlatform::current().unwrap(),
+            Some(&dirs),
+            &install_options(),
+        )
+        .unwrap();
+        assert!(exe.starts_with(&dirs.main));
+        assert!(!dirs.main.read_dir().unwrap().any(|e| e
+            .unwrap()
+            .file_name()
+            .to_str()
+            .unwrap()
+            .ends_with(".temp")));
+
+        // The script must find the install without downloading.
+        fs::remove_dir_all(&archive_dir).unwrap();
+        assert_eq!(
+            format!("{}\n", exe.display()),
+            run_script(&script, dir.path(), &[("COMMASLASH_PRINT_PATH", "1")])
+        );
+        assert_eq!("ok\n", run_script(&script, dir.path(), &[]));
+    }
+
+    #[test]
+    fn test_fetch_script_cache_dir_expr() {
+        let dir = TempDir::new().unwrap();
+        let cache_dir = dir.path().join("ci-cache");
+        let options = GenOptions {
+            cache_dir_expr: Some(cache_dir.to_str().unwrap().to_owned()),
+            ..GenOptions::default()
+        };
+        let script = write_test_script(
+            dir.path(),
+            &dir.path().join("archive"),
+            "bin/tool",
+            &options,
+        );
+
+        let exe = fetch_script(
+            &script,
+            TargetPlatform::current().unwrap(),
+            None,
+            &install_options(),
+        )
+        .unwrap();
+        assert!(exe.starts_with(cache_dir.join("commaslash")));
+        assert_eq!(
+            format!("{}\n", exe.display()),
+            run_script(&script, dir.path(), &[("COMMASLASH_PRINT_PATH", "1")])
+        );
+    }
+
+    #[test]
+    fn test_fetch_script_archive_store() {
+        let dir = TempDir::new().unwrap();
+        let archive_dir = dir.path().join("archive");
+        let script =
+            write_test_script(dir.path(), &archive_dir, "bin/tool", &GenOptions::default());
+        let digest =
+            Digest::of_file(DigestAlgorithm::Sha256, &archive_dir.join("archive.zip")).unwrap();
+        let dirs = test_cache_dirs(dir.path(), "cache");
+        let install_dir = dirs.main.join(digest.cache_name());
+
+        let keep_archives = InstallOptions {
+            keep_archives: true,
+            ..install_options()
+        };
+        let current = TargetPlatform::current().unwrap();
+        fetch_script(&script, current, Some(&dirs), &keep_archives).unwrap();
+        assert!(dirs
+            .main
+            .join("archives")
+            .join(digest.cache_name())
+            .is_file());
+
+        // Reinstall from the archive store.
+        fs::remove_dir_all(&archive_dir).unwrap();
+        fs::remove_dir_all(&install_dir).unwrap();
+        let offline = InstallOptions {
+            offline: true,
+            ..install_options()
+        };
+        fetch_script(&script, current, Some(&dirs), &offline).unwrap();
+
+        // Scripts use the same archive store.
+        fs::remove_dir_all(&install_dir).unwrap();
+        assert_eq!(
+            "ok\n",
+            run_script(&script, dir.path(), &[("COMMASLASH_OFFLINE", "1")])
+        );
+        assert!(install_dir.exists());
+    }
+
+    #[test]
+    fn test_fetch_strip_components() {
+        for extension in ["zip", "tar.gz", "tar.xz"] {
+            fetch_strip_components(extension);
+        }
+    }
+
+    fn fetch_strip_components(extension: &str) {
+        let dir = TempDir::new().unwrap();
+        let archive = dir.path().join(format!("tool-1.0.{extension}"));
+        let entries: &[(&str, &[u8], u32)] =
+            &[("tool-1.0/bin/tool", b"#!/bin/sh\necho ok\n", 0o755)];
+        match extension {
+            "zip" => write_zip(&archive, entries),
+            _ => write_tar(&archive, entries),
+        }
+        let script = dir.path().join("tool");
+        write_spec_script(
+            &script,
+            &format!(
+                "url=file://{} path=bin/tool strip_components=1",
+                archive.display()
+            ),
+            &GenOptions::default(),
+        );
+        let dirs = test_cache_dirs(dir.path(), "cache");
+
+        let exe = fetch_script(
+            &script,
+            TargetPlatform::current().unwrap(),
+            Some(&dirs),
+            &install_options(),
+        )
+        .unwrap();
+        let install_dir = exe.parent().unwrap().parent().unwrap();
+        assert_eq!(Some(dirs.main.as_path()), install_dir.parent());
+        assert_eq!(
+            format!("{}\n", exe.display()),
+            run_script(&script, dir.path(), &[("COMMASLASH_PRINT_PATH", "1")])
+        );
+        assert_eq!("ok\n", run_script(&script, dir.path(), &[]));
+    }
+
+    #[test]
+    fn test_fetch_post_extract() {
+        let dir = TempDir::new().unwrap();
+        let archive = dir.path().join("tool.zip");
+        write_zip(
+            &archive,
+            &[
+                ("libexec/tool", b"#!/bin/sh\necho ok\n", 0o644),
+                ("share/doc/README", b"", 0o644),
+            ],
+        );
+        let script = dir.path().join("tool");
+        let spec = format!(
+            "url=file://{} path=bin/tool chmod_x=libexec/tool symlink=bin->libexec remove=share",
+            archive.display()
+        );
+        write_spec_script(&script, &spec, &GenOptions::default());
+        let dirs = test_cache_dirs(dir.path(), "native");
+
+        let exe = fetch_script(
+            &script,
+            TargetPlatform::current().unwrap(),
+            Some(&dirs),
+            &install_options(),
+        )
+        .unwrap();
+        assert_eq!("ok\n", run_script(&exe, dir.path(), &[]));
+        let native = exe.parent().unwrap().parent().unwrap();
+        assert!(!native.join("share").exists());
+
+        let exe = run_script(&script, dir.path(), &[("COMMASLASH_PRINT_PATH", "1")]);
+        let installed = Path::new(exe.trim_end())
+            .parent()
+            .unwrap()
+            .parent()
+            .unwrap();
+        assert_ne!(native, installed);
+        assert_eq!(
+            fs::read(native.with_extension("manifest")).unwrap(),
+            fs::read(installed.with_extension("manifest")).unwrap()
+        );
+        assert_eq!("ok\n", run_script(&script, dir.path(), &[]));
+    }
+
+    #[test]
+    fn test_fetch_post_extract_errors() {
+        fetch_post_extract_error(
+            |archive| {
+                write_zip(
+                    archive,
+                    &[
+                        ("libexec/tool", b"#!/bin/sh\necho ok\n", 0o755),
+                        ("bin/tool", b"", 0o755),
+                    ],
+                )
+            },
+            "path=libexec/tool symlink=bin->libexec",
+            "cannot create symlink `bin`: it already exists",
+        );
+        fetch_post_extract_error(
+            |archive| {
+                write_zip(
+                    archive,
+                    &[
+                        ("libexec/tool", b"#!/bin/sh\necho ok\n", 0o644),
+                        ("bin/README", b"", 0o644),
+                    ],
+                )
+            },
+            "path=bin/tool symlink=bin/tool->../libexec/tool chmod_x=bin/tool",
+            "cannot chmod_x `bin/tool`: it is a symlink",
+        );
+    }
+
+    #[test]
+    fn test_fetch_post_extract_symlink_parent() {
+        let home = TempDir::new().unwrap();
+        let x = home.path().join("x");
+        fs::write(&x, "").unwrap();
+        let write_archive = |archive: &Path| {
+            let mut zip = zip::ZipWriter::new(fs::File::create(archive).unwrap());
+            let options = SimpleFileOptions::default().unix_permissions(0o755);
+            zip.start_file("bin/tool", options).unwrap();
+            zip.write_all(b"#!/bin/sh\necho ok\n").unwrap();
+            zip.add_symlink("lib", home.path().to_str().unwrap(), options)
+                .unwrap();
+            zip.finish().unwrap();
+        };
+        for (spec, expected) in [
+            ("remove=lib/x", "cannot remove `lib/x`"),
+            ("chmod_x=lib/x", "cannot chmod_x `lib/x`"),
+            ("symlink=lib/y->x", "cannot create symlink `lib/y`"),
+        ] {
+            fetch_post_extract_error(
+                write_archive,
+                &format!("path=bin/tool {spec}"),
+                &format!("{expected}: `lib` is a symlink"),
+            );
+        }
+        assert_eq!(
+            0o644,
+            fs::metadata(&x).unwrap().permissions().mode() & 0o777
+        );
+        assert!(!home.path().join("y").exists());
+    }
+
+    /// Both the script and `fetch` fail with `expected`.
+    fn fetch_post_extract_error(write_archive: impl FnOnce(&Path), spec: &str, expected: &str) {
+        let dir = TempDir::new().unwrap();
+        let archive = dir.path().join("tool.zip");
+        write_archive(&archive);
+        let script = dir.path().join("tool");
+        write_spec_script(
+            &script,
+            &format!("url=file://{} {spec}", archive.display()),
+            &GenOptions::default(),
+        );
+        let dirs = test_cache_dirs(dir.path(), "native");
+
+        let error = fetch_script(
+            &script,
+            TargetPlatform::current().unwrap(),
+            Some(&dirs),
+            &install_options(),
+        )
+        .unwrap_err();
+        assert!(format!("{error:#}").contains(expected), "{error:#}");
+
+        fs::create_dir_all(dir.path().join("tmp")).unwrap();
+        let output = script_command(&script, dir.path()).output().unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!output.status.success());
+        assert!(stderr.contains(expected), "{stderr}");
+    }
+}
@@ -0,0 +1,461 @@
+use crate::cache;
+use crate::cache::{commaslash_dirs, CacheEntry};
+use crate::digest::Digest;
+use crate::script::find_scripts;
+use crate::target_platform::TargetPlatform;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Temp files younger than this may belong to a script which has not taken the lock yet.
+const TEMP_GRACE_PERIOD: Duration = Duration::from_secs(60 * 60);
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Remove unused installs and leftover temp and lock files from the cache.
+#[derive(clap::Args)]
+pub(crate) struct GcArgs {
+    /// Remove installs not used for this many days.
+    #[clap(long, value_name = "days")]
+    max_age_days: Option<u64>,
+    /// Remove installs not referenced by any script under this directory.
+    /// Caches of the scripts are collected too, if they are generated with `--cache-dir-expr`.
+    /// Can be specified multiple times. Refuses to run if a script under it
+    /// cannot be parsed, e.g. one generated before specs were embedded.
+    #[clap(long, value_name = "dir")]
+    root: Vec<PathBuf>,
+    /// Only print what would be removed.
+    #[clap(long)]
+    dry_run: bool,
+}
+
+struct GcPolicy {
+    now: SystemTime,
+    max_age: Option<Duration>,
+    /// `None` if roots are not specified.
+    referenced: Option<HashSet<Digest>>,
+    dry_run: bool,
+}
+
+impl GcPolicy {
+    fn should_remove(&self, entry: &CacheEntry, last_used: Option<SystemTime>) -> bool {
+        if let Some(referenced) = &self.referenced {
+            if !referenced.contains(&entry.digest) {
+                return true;
+            }
+        }
+        if let Some(max_age) = self.max_age {
+            if let Some(last_used) = last_used {
+                if self.age(last_used) > max_age {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    fn age(&self, time: SystemTime) -> Duration {
+        self.now.duration_since(time).unwrap_or_default()
+    }
+
+    fn remove(&self, path: &Path) -> anyhow::Result<()> {
+        let Ok(meta) = path.symlink_metadata() else {
+            return Ok(());
+        };
+        println!("  remove {}", path.display());
+        if self.dry_run {
+            return Ok(());
+        }
+        if meta.is_dir() {
+            fs::remove_dir_all(path)?;
+        } else {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    /// Temp file is old enough to be left by a process which was killed.
+    fn is_abandoned(&self, path: &Path) -> bool {
+        match path.symlink_metadata().and_then(|m| m.modified()) {
+            Ok(modified) => self.age(modified) >= TEMP_GRACE_PERIOD,
+            Err(_) => false,
+        }
+    }
+
+    /// The fast path records only the first use after a gc, so an install whose marker
+    /// is newer than the stamp of the previous gc may have been used just now.
+    /// A missing stamp is older than any marker.
+    fn used_since_stamp(entry: &CacheEntry, stamp: Option<SystemTime>) -> bool {
+        match entry
+            .used_marker_path()
+            .metadata()
+            .and_then(|m| m.modified())
+        {
+            Ok(marker) => stamp.is_none_or(|stamp| marker > stamp),
+            Err(_) => false,
+        }
+    }
+
+    fn gc_entry(&self, entry: &CacheEntry, stamp: Option<SystemTime>) -> anyhow::Result<()> {
+        println!("{}", entry.summary(self.now));
+
+        // Don't create lock files in dry run.
+        let _lock = if self.dry_run {
+            None
+        } else {
+            match entry.try_lock()? {
+                Some(lock) => Some(lock),
+                None => {
+                    println!("  locked by another process, skipping");
+                    return Ok(());
+                }
+            }
+        };
+
+        let mut remaining = Vec::new();
+        for temp in [entry.temp_dir(), entry.archive_temp_path()] {
+            if self.is_abandoned(&temp) {
+                self.remove(&temp)?;
+            } else if temp.exists() {
+                remaining.push(temp);
+            }
+        }
+
+        let used_since_stamp = Self::used_since_stamp(entry, stamp);
+        let last_used = if used_since_stamp {
+            Some(self.now)
+        } else {
+            entry.last_used()
+        };
+        let install_dir = entry.install_dir();
+        if self.should_remove(entry, last_used) {
+            let temp_dir = entry.temp_dir();
+            if install_dir.exists() && !self.dry_run && !temp_dir.exists() {
+                // Make install disappear atomically for the fast path.
+                fs::rename(&install_dir, &temp_dir)?;
+                println!("  remove {}", install_dir.display());
+                fs::remove_dir_all(&temp_dir)?;
+            } else {
+                self.remove(&install_dir)?;
+            }
+            self.remove(&entry.manifest_path())?;
+            self.remove(&entry.archive_path())?;
+            self.remove(&entry.used_marker_path())?;
+        } else {
+            if used_since_stamp && !self.dry_run {
+                // Keep the use for the next gc: the stamp is refreshed after this one.
+                fs::File::options()
+                    .write(true)
+                    .open(entry.used_marker_path())?
+                    .set_modified(self.now)?;
+            }
+            remaining.extend([install_dir, entry.archive_path()]);
+        }
+
+        if !remaining.iter().any(|path| path.exists()) {
+            // There's a race with a process which opens the lock file before we remove it,
+            // but scripts tolerate failures to lock.
+            self.remove(&entry.lock_path())?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn gc(args: &GcArgs) -> anyhow::Result<()> {
+    let target_platform = TargetPlatform::current()?;
+    let mut scripts = Vec::new();
+    for root in &args.root {
+        let found = find_scripts(root)?;
+        if let Some(unparsed) = found.unparsed.first() {
+            // Its installs would look unreferenced.
+            return Err(anyhow::anyhow!(
+                "Cannot tell which installs `{}` uses; regenerate it or remove it from `--root`",
+                unparsed.display()
+            ));
+        }
+        scripts.extend(found.scripts);
+    }
+    let dirs = commaslash_dirs(
+        &target_platform.os(),
+        scripts.iter().map(|(_path, script)| &script.options),
+    )?;
+    let referenced = if args.root.is_empty() {
+        None
+    } else {
+        Some(
+            scripts
+                .iter()
+                .flat_map(|(_path, script)| script.spec.specs.values().map(|spec| spec.digest))
+                .collect(),
+        )
+    };
+    let policy = GcPolicy {
+        now: SystemTime::now(),
+        max_age: args.max_age_days.map(|days| DAY * days as u32),
+        referenced,
+        dry_run: args.dry_run,
+    };
+    gc_dirs(&dirs, &policy)
+}
+
+/// Remove an entry regardless of its use, printing what is removed like `gc` does.
+pub(crate) fn remove_entry(entry: &CacheEntry, dry_run: bool) -> anyhow::Result<()> {
+    let policy = GcPolicy {
+        now: SystemTime::now(),
+        max_age: None,
+        referenced: Some(HashSet::new()),
+        dry_run,
+    };
+    policy.gc_entry(entry, None)
+}
+
+fn gc_dirs(commaslash_dirs: &[PathBuf], policy: &GcPolicy) -> anyhow::Result<()> {
+    for commaslash_dir in commaslash_dirs {
+        let stamp_path = cache::used_stamp_path(commaslash_dir);
+        let stamp = stamp_path.metadata().and_then(|m| m.modified()).ok();
+        for entry in cache::list_entries(commaslash_dir)? {
+            policy.gc_entry(&entry, stamp)?;
+        }
+        if !policy.dry_run && commaslash_dir.exists() {
+            // Scripts record the next use of every install from now on.
+            fs::File::create(&stamp_path)?.set_modified(policy.now)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cache::CacheDirs;
+    use crate::digest::{Digest, DigestAlgorithm};
+    use crate::gc::{gc, gc_dirs, GcArgs, GcPolicy, DAY};
+    use crate::gen::GenOptions;
+    use crate::install::{install, InstallOptions};
+    use crate::script::{Script, HEADER};
+    use crate::target_platform::TargetPlatform;
+    use crate::testutil::{test_cache_dirs, write_test_script};
+    use std::collections::HashSet;
+    use std::fs;
+    use std::path::Path;
+    use std::time::{Duration, SystemTime};
+    use tempfile::TempDir;
+
+    struct Fixture {
+        _dir: TempDir,
+        dirs: CacheDirs,
+        used: Digest,
+        unused: Digest,
+        abandoned: Digest,
+    }
+
+    fn install_script(dir: &Path, dirs: &CacheDirs, path: &str) -> Digest {
+        let script = write_test_script(dir, &dir.join("archive"), path, &GenOptions::default());
+        let script = Script::read(&script).unwrap();
+        let spec = script
+            .spec
+            .specs
+            .get(&TargetPlatform::current().unwrap())
+            .unwrap();
+        let options = InstallOptions {
+            seed_dir: None,
+            offline: false,
+            keep_archives: true,
+            verify: false,
+        };
+        install(dirs, &options, spec).unwrap();
+        spec.digest
+    }
+
+    fn fixture() -> Fixture {
+        let dir = TempDir::new().unwrap();
+        let dirs = test_cache_dirs(dir.path(), "cache");
+        let used = install_script(&dir.path().join("used"), &dirs, "bin/used");
+        let unused = install_script(&dir.path().join("unused"), &dirs, "bin/unused");
+        let abandoned = Digest::from_hex(
+            DigestAlgorithm::Sha256,
+            "1717171717171717171717171717171717171717171717171717171717171717",
+        )
+        .unwrap();
+        fs::create_dir(dirs.main.join(format!("{}.temp", abandoned.cache_name()))).unwrap();
+        fs::write(
+            dirs.main.join(format!("{}.lock", abandoned.cache_name())),
+            "",
+        )
+        .unwrap();
+        Fixture {
+            _dir: dir,
+            dirs,
+            used,
+            unused,
+            abandoned,
+        }
+    }
+
+    fn names(dirs: &CacheDirs) -> Vec<String> {
+        let mut names: Vec<String> = dirs
+            .main
+            .read_dir()
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_gc_unreferenced() {
+        let f = fixture();
+        gc_dirs(
+            &f.dirs.all().map(Path::to_owned),
+            &GcPolicy {
+                now: SystemTime::now() + 2 * Duration::from_secs(60 * 60),
+                max_age: None,
+                referenced: Some(HashSet::from([f.used])),
+                dry_run: false,
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            vec![
+                f.used.cache_name(),
+                format!("{}.lock", f.used.cache_name()),
+                format!("{}.manifest", f.used.cache_name()),
+                "archives".to_owned(),
+                "used.stamp".to_owned(),
+            ],
+            names(&f.dirs)
+        );
+        assert!(f
+            .dirs
+            .main
+            .join("archives")
+            .join(f.used.cache_name())
+            .exists());
+        assert!(!f
+            .dirs
+            .main
+            .join("archives")
+            .join(f.unused.cache_name())
+            .exists());
+    }
+
+    #[test]
+    fn test_gc_dry_run() {
+        let f = fixture();
+        let before = names(&f.dirs);
+        gc_dirs(
+            &f.dirs.all().map(Path::to_owned),
+            &GcPolicy {
+                now: SystemTime::now() + 10 * DAY,
+                max_age: Some(DAY),
+                referenced: Some(HashSet::new()),
+                dry_run: true,
+            },
+        )
+        .unwrap();
+        assert_eq!(before, names(&f.dirs));
+    }
+
+    #[test]
+    fn test_gc_max_age() {
+        let f = fixture();
+
+        // Recent temp files are kept: they may belong to a script which is starting.
+        gc_dirs(
+            &f.dirs.all().map(Path::to_owned),
+            &GcPolicy {
+                now: SystemTime::now(),
+                max_age: Some(DAY),
+                referenced: None,
+                dry_run: false,
+            },
+        )
+        .unwrap();
+        assert!(f
+            .dirs
+            .main
+            .join(format!("{}.temp", f.abandoned.cache_name()))
+            .exists());
+        assert!(f.dirs.main.join(f.used.cache_name()).exists());
+
+        gc_dirs(
+            &f.dirs.all().map(Path::to_owned),
+            &GcPolicy {
+                now: SystemTime::now() + 2 * DAY,
+                max_age: Some(DAY),
+                referenced: None,
+                dry_run: false,
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            vec!["archives".to_owned(), "used.stamp".to_owned()],
+            names(&f.dirs)
+        );
+    }
+
+    #[test]
+    fn test_gc_used_marker() {
+        let f = fixture();
+        let now = SystemTime::now();
+        let set_modified = |path: &Path, time: SystemTime| {
+            fs::File::options()
+                .write(true)
+                .open(path)
+                .unwrap()
+                .set_modified(time)
+                .unwrap()
+        };
+        let modified = |path: &Path| fs::metadata(path).unwrap().modified().unwrap();
+        // Previous gc three days ago, first use since then two days ago.
+        let stamp = f.dirs.main.join("used.stamp");
+        set_modified(&stamp, now - 3 * DAY);
+        let used_marker = f.dirs.main.join(format!("{}.used", f.used.cache_name()));
+        fs::File::create(&used_marker).unwrap();
+        set_modified(&used_marker, now - 2 * DAY);
+
+        let policy = |now| GcPolicy {
+            now,
+            max_age: Some(DAY),
+            referenced: None,
+            dry_run: false,
+        };
+        gc_dirs(&f.dirs.all().map(Path::to_owned), &policy(now + 2 * DAY)).unwrap();
+        // Later uses are not recorded, so it may have been used just now.
+        assert_eq!(
+            vec![
+                f.used.cache_name(),
+                format!("{}.lock", f.used.cache_name()),
+                format!("{}.manifest", f.used.cache_name()),
+                format!("{}.used", f.used.cache_name()),
+                "archives".to_owned(),
+                "used.stamp".to_owned(),
+            ],
+            names(&f.dirs)
+        );
+        assert_eq!(now + 2 * DAY, modified(&used_marker));
+        assert_eq!(now + 2 * DAY, modified(&stamp));
+
+        // Not used since the previous gc.
+        gc_dirs(&f.dirs.all().map(Path::to_owned), &policy(now + 4 * DAY)).unwrap();
+        assert_eq!(
+            vec!["archives".to_owned(), "used.stamp".to_owned()],
+            names(&f.dirs)
+        );
+    }
+
+    #[test]
+    fn test_gc_refuses_unparsed_scripts() {
+        let dir = TempDir::new().unwrap();
+        let old = dir.path().join("old");
+        fs::write(&old, format!("{HEADER}exec true\n")).unwrap();
+        let args = GcArgs {
+            max_age_days: None,
+            root: vec![dir.path().to_owned()],
+            dry_run: true,
+        };
+        let error = gc(&args).unwrap_err();
+        assert!(format!("{error}").contains("regenerate it"), "{error}");
+    }
+}
@@ -1,9 +1,13 @@
 use crate::os::Os;
-use crate::sh::{ShArg, ShArgEscape, ShArgRaw};
-use crate::shx;
-use crate::shx::{euid_command, exec_if_exists, file_owner_command};
+use crate::rel_path::RelPath;
+use crate::script::{CACHE_DIR_EXPR_PREFIX, HEADER, SOURCE_PREFIX, SPEC_PREFIX, VERSION_PREFIX};
+use crate::sh::{ShArg, ShArgEscape, ShArgRaw, ShDqEscape};
+use crate::shx::{euid_command, exec_command, exec_if_exists, file_owner_command, EXEC_FUNCTION};
+use crate::spec::parse::INSTALL_DIR_PLACEHOLDER;
+use crate::spec::post_extract::PostExtract;
 use crate::spec::resolve::{ResolvedSpec, ResolvedTargetSpec};
 use crate::target_platform::TargetPlatform;
+use ordinal_map::map::OrdinalMap;
 use std::fmt::{Display, Write};
 
 #[must_use]
@@ -16,36 +20,146 @@ fn die(message: impl Display) -> String {
     die_impl(ShArgEscape(message.to_string()))
 }
 
-fn commaslash_dir(os: &Os) -> String {
-    format!("{}/commaslash", os.cache_dir_expr())
+/// Variable which overrides `commaslash_dir` at runtime.
+pub(crate) const CACHE_DIR_VAR: &str = "COMMASLASH_CACHE_DIR";
+/// When set to `1`, scripts fail instead of downloading.
+pub(crate) const OFFLINE_VAR: &str = "COMMASLASH_OFFLINE";
+/// Directory with pre-downloaded archives named like install dirs.
+pub(crate) const SEED_DIR_VAR: &str = "COMMASLASH_SEED_DIR";
+/// When set to `1`, downloaded archives are kept in `$commaslash_dir/archives`.
+pub(crate) const KEEP_ARCHIVES_VAR: &str = "COMMASLASH_KEEP_ARCHIVES";
+/// When set to `1`, installs are checked against `$install_dir.manifest` before use.
+pub(crate) const VERIFY_VAR: &str = "COMMASLASH_VERIFY";
+
+fn commaslash_dir(cache_dir_expr: &str) -> String {
+    format!("${{{CACHE_DIR_VAR}:-{cache_dir_expr}/commaslash}}")
 }
 
 fn backup_commaslash_dir() -> &'static str {
     "${TMPDIR:-/tmp}/commaslash-$(id -u)"
 }
 
-fn install_dir(target_platform: &TargetPlatform, spec: &ResolvedTargetSpec) -> String {
-    format!("{}/{}", commaslash_dir(&target_platform.os()), spec.sha256)
+fn install_dir(commaslash_dir: &str, spec: &ResolvedTargetSpec) -> String {
+    format!("{}/{}", commaslash_dir, spec.digest.cache_name())
+}
+
+/// Quoted path in the archive store, valid after `commaslash_dir` is assigned.
+fn stored_archive(spec: &ResolvedTargetSpec) -> String {
+    format!(r#""$commaslash_dir/archives/{}""#, spec.digest.cache_name())
 }
 
-fn backup_install_dir(spec: &ResolvedTargetSpec) -> String {
-    format!("{}/{}", backup_commaslash_dir(), spec.sha256)
+/// Expression to be placed inside double quotes.
+fn exe_path(commaslash_dir: &str, spec: &ResolvedTargetSpec) -> String {
+    format!(
+        "{}/{}",
+        install_dir(commaslash_dir, spec),
+        ShDqEscape(spec.path.as_str())
+    )
 }
 
-fn exe_path(target_platform: &TargetPlatform, spec: &ResolvedTargetSpec) -> String {
-    format!("{}/{}", install_dir(target_platform, spec), spec.path)
+/// `arg`, `interpreter_arg` or `env` value as a double-quoted word, with `{install_dir}` replaced
+/// by `install_dir` expression.
+fn dq_with_install_dir(value: &str, install_dir: &str) -> String {
+    let parts: Vec<String> = value
+        .split(INSTALL_DIR_PLACEHOLDER)
+        .map(|part| ShDqEscape(part).to_string())
+        .collect();
+    format!(r#""{}""#, parts.join(install_dir))
 }
 
-fn backup_exe_path(spec: &ResolvedTargetSpec) -> String {
-    format!("{}/{}", backup_install_dir(spec), spec.path)
+/// Quoted `NAME=value` words and command of `spec` for `exec_command`.
+/// Arguments are expressions to be placed inside double quotes.
+fn exec_env_and_command(
+    install_dir: &str,
+    exe_path: &str,
+    spec: &ResolvedTargetSpec,
+) -> (Vec<String>, Vec<String>) {
+    let env = spec
+        .env
+        .iter()
+        .map(|(name, value)| dq_with_install_dir(&format!("{name}={value}"), install_dir))
+        .collect();
+    let mut command = Vec::new();
+    if let Some(interpreter) = &spec.interpreter {
+        command.push(format!(r#""{}""#, ShDqEscape(interpreter)));
+        command.extend(
+            spec.interpreter_args
+                .iter()
+                .map(|arg| dq_with_install_dir(arg, install_dir)),
+        );
+    }
+    command.push(format!(r#""{exe_path}""#));
+    command.extend(
+        spec.args
+            .iter()
+            .map(|arg| dq_with_install_dir(arg, install_dir)),
+    );
+    (env, command)
+}
+
+/// `test` operator which checks that the executable is installed, like `is_installed_exe`.
+fn exe_test(spec: &ResolvedTargetSpec) -> &'static str {
+    match spec.interpreter {
+        Some(_) => "-f",
+        None => "-x",
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct GenOptions {
+    /// Overrides `Os::cache_dir_expr`.
+    pub(crate) cache_dir_expr: Option<String>,
+    /// Version substituted for `{version}` in `sources`.
+    pub(crate) version: Option<String>,
+    /// Specs as given to the generator, made by `ParsedTargetSpec::unpinned`,
+    /// so `commaslash update` can resolve them for another version.
+    pub(crate) sources: OrdinalMap<TargetPlatform, String>,
+}
+
+impl GenOptions {
+    pub(crate) fn validate(&self) -> anyhow::Result<()> {
+        if let Some(cache_dir_expr) = &self.cache_dir_expr {
+            if cache_dir_expr.is_empty() {
+                return Err(anyhow::anyhow!("Empty cache dir expression"));
+            }
+            if cache_dir_expr.contains(|c: char| c == '"' || c.is_control()) {
+                return Err(anyhow::anyhow!(
+                    "Cache dir expression must not contain `\"` or control characters: {cache_dir_expr:?}"
+                ));
+            }
+        }
+        if let Some(version) = &self.version {
+            if version.is_empty() || version.contains(|c: char| c.is_whitespace() || c.is_control())
+            {
+                return Err(anyhow::anyhow!(
+                    "Version must be non-empty and must not contain whitespace: {version:?}"
+                ));
+            }
+        }
+        for source in self.sources.values() {
+            if source.contains(|c: char| c.is_control()) {
+                return Err(anyhow::anyhow!(
+                    "Spec must not contain control characters: {source:?}"
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn cache_dir_expr<'a>(&'a self, os: &Os) -> &'a str {
+        self.cache_dir_expr
+            .as_deref()
+            .unwrap_or_else(|| os.cache_dir_expr())
+    }
 }
 
-pub(crate) struct Gen {
+pub(crate) struct Gen<'a> {
     script: String,
     indent: usize,
+    options: &'a GenOptions,
 }
 
-impl Write for Gen {
+impl Write for Gen<'_> {
     fn write_str(&mut self, s: &str) -> std::fmt::Result {
         for c in s.chars() {
             if c == '\n' {
@@ -62,7 +176,7 @@ impl Write for Gen {
     }
 }
 
-impl Gen {
+impl Gen<'_> {
     pub(crate) fn indented(
         &mut self,
         f: impl FnOnce(&mut Self) -> anyhow::Result<()>,
@@ -97,12 +211,27 @@ impl Gen {
 
     fn fast_path(&mut self, spec: &ResolvedSpec) -> anyhow::Result<()> {
         self.case(ShArgRaw(r#""$(uname -sm)""#.to_owned()), |gen| {
-            for (target_platform, spec) in &spec.specs {
-                let exe_path = exe_path(&target_platform, spec);
-                let backup_exe_path = backup_exe_path(spec);
-                writeln!(gen, r#""{}")"#, target_platform.uname_sm(),)?;
-                for path in &[exe_path, backup_exe_path] {
-                    writeln!(gen, r#"    {}"#, exec_if_exists(path))?;
+            for (target_platforms, spec) in spec_groups(spec) {
+                let commaslash_dir =
+                    commaslash_dir(gen.options.cache_dir_expr(&target_platforms[0].os()));
+                writeln!(gen, "{})", case_pattern(&target_platforms))?;
+                if let Some(interpreter) = &spec.interpreter {
+                    gen.indented(|gen| {
+                        gen.assert_command_exists(
+                            interpreter,
+                            &format!("command -v {}", ShArgEscape(interpreter.clone())),
+                        )
+                    })?;
+                }
+                for dir in [commaslash_dir.as_str(), backup_commaslash_dir()] {
+                    let install_dir = install_dir(dir, spec);
+                    let exe_path = exe_path(dir, spec);
+                    let (env, command) = exec_env_and_command(&install_dir, &exe_path, spec);
+                    writeln!(
+                        gen,
+                        r#"    {}"#,
+                        exec_if_exists(&install_dir, &exe_path, exe_test(spec), &env, &command)
+                    )?;
                 }
                 writeln!(gen, r#"    ;;"#,)?;
             }
@@ -116,6 +245,100 @@ impl Gen {
         Ok(())
     }
 
+    /// Define functions which print the manifest of an install dir
+    /// (see `manifest.rs`) and compare it with the manifest written at extraction.
+    fn define_verify(&mut self) -> anyhow::Result<()> {
+        writeln!(self, "_commaslash_manifest() {{")?;
+        self.indented(|gen| {
+            gen.subprocess(|gen| {
+                writeln!(gen, r#"cd "$1" || exit 1"#)?;
+                writeln!(gen, "{{")?;
+                gen.indented(|gen| {
+                    gen.comment("One `sha256sum` for many files; only symlinks take a fork each.")?;
+                    writeln!(gen, "if command -v sha256sum >/dev/null 2>&1; then")?;
+                    writeln!(gen, "    find . -type f -exec sha256sum {{}} +")?;
+                    writeln!(gen, "else")?;
+                    writeln!(gen, "    find . -type f -exec shasum -a 256 {{}} +")?;
+                    writeln!(gen, "fi")?;
+                    writeln!(
+                        gen,
+                        r#"find . -type l -exec sh -c 'for l; do printf "%s -> " "$l"; readlink "$l"; done' sh {{}} +"#
+                    )?;
+                    Ok(())
+                })?;
+                writeln!(gen, "}} | LC_ALL=C sort")?;
+                Ok(())
+            })
+        })?;
+        writeln!(self, "}}")?;
+        writeln!(self, "_commaslash_verify() {{")?;
+        writeln!(
+            self,
+            r#"    test -f "$1.manifest" && _commaslash_manifest "$1" 2>/dev/null | cmp -s - "$1.manifest""#
+        )?;
+        writeln!(self, "}}")?;
+        Ok(())
+    }
+
+    /// Define a function which marks the install as used and execs the binary,
+    /// or exits if `COMMASLASH_FETCH_ONLY=1`,
+    /// or prints its path if `COMMASLASH_PRINT_PATH=1`.
+    ///
+    /// Arguments are the install dir, the executable, `NAME=value` words to export,
+    /// `--` and the command to exec.
+    ///
+    /// With `COMMASLASH_VERIFY=1` the function returns 1 if the install does not match
+    /// its manifest, so the script proceeds to the next fast path check or reinstalls.
+    fn define_exec(&mut self) -> anyhow::Result<()> {
+        writeln!(self, "{EXEC_FUNCTION}() {{")?;
+        self.indented(|gen| {
+            gen.comment("`_commaslash_installed` is set by the slow path, which has just checked the install.")?;
+            gen.if_fi(
+                &format!(
+                    r#"test "${{{VERIFY_VAR}:-}}" = 1 && test -z "${{_commaslash_installed:-}}" && ! _commaslash_verify "$1""#
+                ),
+                |gen| {
+                    writeln!(gen, r#"echo "$1 does not match its manifest" >&2"#)?;
+                    writeln!(gen, "return 1")?;
+                    Ok(())
+                },
+            )?;
+            gen.comment("Record the first use after each gc; `test` is a builtin, so a fresh marker costs no fork.")?;
+            gen.if_fi(r#"! test "$1.used" -nt "${1%/*}/used.stamp""#, |gen| {
+                writeln!(gen, r#"touch "$1.used" 2>/dev/null || true"#)?;
+                Ok(())
+            })?;
+            writeln!(gen, "shift")?;
+            gen.if_fi(r#"test "${COMMASLASH_FETCH_ONLY:-}" = 1"#, |gen| {
+                writeln!(gen, "exit 0")?;
+                Ok(())
+            })?;
+            gen.if_fi(r#"test "${COMMASLASH_PRINT_PATH:-}" = 1"#, |gen| {
+                gen.comment("The cache dir may be relative, e.g. `XDG_CACHE_HOME=.cache`.")?;
+                gen.case(ShArgRaw(r#""$1""#.to_owned()), |gen| {
+                    writeln!(gen, r#"/*) printf '%s\n' "$1" ;;"#)?;
+                    writeln!(
+                        gen,
+                        r#"*) printf '%s/%s\n' "$(cd "$(dirname "$1")" && pwd -P)" "$(basename "$1")" ;;"#
+                    )?;
+                    Ok(())
+                })?;
+                writeln!(gen, "exit 0")?;
+                Ok(())
+            })?;
+            writeln!(gen, "shift")?;
+            writeln!(gen, r#"while test "$1" != --; do"#)?;
+            writeln!(gen, r#"    export "$1""#)?;
+            writeln!(gen, "    shift")?;
+            writeln!(gen, "done")?;
+            writeln!(gen, "shift")?;
+            writeln!(gen, r#"exec "$@""#)?;
+            Ok(())
+        })?;
+        writeln!(self, "}}")?;
+        Ok(())
+    }
+
     fn _define_die(&mut self) -> anyhow::Result<()> {
         writeln!(self, r#"die() {{"#)?;
         writeln!(self, r#"    echo "$@" >&2"#)?;
@@ -156,83 +379,249 @@ impl Gen {
         Ok(())
     }
 
+    /// Copy verified `$temp_dir/zip` to the archive store if requested.
+    fn store_archive(&mut self, spec: &ResolvedTargetSpec) -> anyhow::Result<()> {
+        let stored_archive = stored_archive(spec);
+        self.if_fi(
+            &format!(r#"test "${{{KEEP_ARCHIVES_VAR}:-}}" = 1 && ! test -f {stored_archive}"#),
+            |gen| {
+                writeln!(gen, r#"mkdir -p "$commaslash_dir/archives""#)?;
+                writeln!(
+                    gen,
+                    r#"cp "$temp_dir/zip" "$commaslash_dir/archives/{}.temp""#,
+                    spec.digest.cache_name()
+                )?;
+                writeln!(
+                    gen,
+                    r#"mv "$commaslash_dir/archives/{}.temp" {stored_archive}"#,
+                    spec.digest.cache_name()
+                )?;
+                Ok(())
+            },
+        )
+    }
+
+    /// Put the archive into `$temp_dir/zip`, downloading it if needed.
+    fn obtain_archive(&mut self, spec: &ResolvedTargetSpec) -> anyhow::Result<()> {
+        let seed_archive = format!(r#""${SEED_DIR_VAR}/{}""#, spec.digest.cache_name());
+        let stored_archive = stored_archive(spec);
+        writeln!(
+            self,
+            r#"if test -n "${{{SEED_DIR_VAR}:-}}" && test -f {seed_archive}; then"#
+        )?;
+        writeln!(self, r#"    cp {seed_archive} "$temp_dir/zip""#)?;
+        writeln!(self, r#"elif test -f {stored_archive}; then"#)?;
+        writeln!(self, r#"    cp {stored_archive} "$temp_dir/zip""#)?;
+        writeln!(self, r#"elif test "${{{OFFLINE_VAR}:-}}" = 1; then"#)?;
+        writeln!(
+            self,
+            "    {}",
+            die_impl(ShArgRaw(format!(
+                r#""{OFFLINE_VAR}=1: not downloading {} ({}) to install $exe""#,
+                ShDqEscape(&spec.url),
+                spec.digest,
+            )))
+        )?;
+        writeln!(self, "else")?;
+        writeln!(
+            self,
+            r#"    curl --globoff --location --retry 3 --fail --silent --show-error --output "$temp_dir/zip" {}"#,
+            shlex::try_quote(&spec.url)?
+        )?;
+        writeln!(self, "fi")?;
+        Ok(())
+    }
+
+    /// Replace `$temp_dir/<name>` with the only directory `strip_components` levels deep in it.
+    fn strip_components(&mut self, spec: &ResolvedTargetSpec) -> anyhow::Result<()> {
+        let unpacked = format!(r#""$temp_dir/{}""#, spec.digest.cache_name());
+        let levels: Vec<String> = (1..=spec.strip_components).map(|i| i.to_string()).collect();
+        self.assign_raw("stripped", &unpacked)?;
+        writeln!(self, "for _ in {}; do", levels.join(" "))?;
+        self.indented(|gen| {
+            gen.if_fi(r#"test "$(ls -A "$stripped" | wc -l)" -ne 1"#, |gen| {
+                gen.die("cannot strip components: archive level is not a single directory")
+            })?;
+            writeln!(gen, r#"stripped="$stripped/$(ls -A "$stripped")""#)?;
+            gen.if_fi(r#"test -L "$stripped" || ! test -d "$stripped""#, |gen| {
+                gen.die("cannot strip components: archive level is not a single directory")
+            })
+        })?;
+        writeln!(self, "done")?;
+        writeln!(self, r#"mv "$stripped" "$temp_dir/stripped""#)?;
+        writeln!(self, "rm -rf {unpacked}")?;
+        writeln!(self, r#"mv "$temp_dir/stripped" {unpacked}"#)?;
+        Ok(())
+    }
+
+    /// Apply post-extract actions to `$temp_dir/<name>`, like `install::post_extract`.
+    fn post_extract(&mut self, spec: &ResolvedTargetSpec) -> anyhow::Result<()> {
+        let unpacked = format!("$temp_dir/{}", spec.digest.cache_name());
+        for action in &spec.post_extract {
+            match action {
+                PostExtract::ChmodX(path) => {
+                    self.die_if_symlink_parent(&unpacked, "chmod_x", path)?;
+                    let file = format!(r#""{unpacked}/{}""#, ShDqEscape(path.as_str()));
+                    // `chmod` follows symlinks, possibly out of the install.
+                    self.if_fi(&format!("test -L {file}"), |gen| {
+                        gen.die(format_args!("cannot chmod_x `{path}`: it is a symlink"))
+                    })?;
+                    writeln!(self, "chmod a+x {file}")?;
+                }
+                PostExtract::Symlink { link, target } => {
+                    self.die_if_symlink_parent(&unpacked, "create symlink", link)?;
+                    let file = format!(r#""{unpacked}/{}""#, ShDqEscape(link.as_str()));
+                    // `ln -s` would create the link inside an existing directory.
+                    self.if_fi(&format!("test -e {file} || test -L {file}"), |gen| {
+                        gen.die(format_args!(
+                            "cannot create symlink `{link}`: it already exists"
+                        ))
+                    })?;
+                    writeln!(self, r#"ln -s "{}" {file}"#, ShDqEscape(target))?;
+                }
+                PostExtract::Remove(path) => {
+                    self.die_if_symlink_parent(&unpacked, "remove", path)?;
+                    writeln!(self, r#"rm -rf "{unpacked}/{}""#, ShDqEscape(path.as_str()))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Post-extract actions on `path` would follow a symlinked parent directory,
+    /// possibly out of the install.
+    fn die_if_symlink_parent(
+        &mut self,
+        unpacked: &str,
+        action: &str,
+        path: &RelPath,
+    ) -> anyhow::Result<()> {
+        for parent in path.parents() {
+            let dir = format!(r#""{unpacked}/{}""#, ShDqEscape(parent.as_str()));
+            self.if_fi(&format!("test -L {dir}"), |gen| {
+                gen.die(format_args!(
+                    "cannot {action} `{path}`: `{parent}` is a symlink"
+                ))
+            })?;
+        }
+        Ok(())
+    }
+
     fn slow_path_for_target(
         &mut self,
         target_platform: &TargetPlatform,
         spec: &ResolvedTargetSpec,
     ) -> anyhow::Result<()> {
-        let sha256_command = target_platform.os().sha256_command();
+        let digest_command = target_platform.os().digest_command(spec.digest.algorithm());
 
         self.assert_command_exists(
             spec.archive_format.command(),
             spec.archive_format.test_command(),
         )?;
         self.assert_command_exists(
-            sha256_command.command(),
-            sha256_command.test_command().as_str(),
+            digest_command.command(),
+            digest_command.test_command().as_str(),
         )?;
 
-        writeln!(
-            self,
-            r#"commaslash_dir="{}""#,
-            commaslash_dir(&target_platform.os())
+        self.assign_raw(
+            "commaslash_dir",
+            format_args!(
+                r#""{}""#,
+                commaslash_dir(self.options.cache_dir_expr(&target_platform.os()))
+            ),
         )?;
 
-        self.comment("Checking if we own the directory")?;
-        writeln!(self, r#"_commaslash_dir_x="$commaslash_dir""#)?;
-        writeln!(self, r#"while ! test -e "$_commaslash_dir_x"; do"#)?;
-        writeln!(
-            self,
-            r#"    _commaslash_dir_x="$(dirname "$_commaslash_dir_x")""#
-        )?;
-        writeln!(self, r#"done"#)?;
-        self.comment("If we don't own the directory, we will cache in a temporary directory")?;
-        writeln!(
-            self,
-            r#"if test "$({})" -ne "$({})"; then"#,
-            file_owner_command(r#""$_commaslash_dir_x""#),
-            euid_command()
-        )?;
-        writeln!(
-            self,
-            r#"    commaslash_dir="${{TMPDIR:-/tmp}}/commaslash-$({})""#,
-            euid_command()
-        )?;
-        writeln!(self, r#"fi"#)?;
+        self.if_fi(&format!(r#"test -z "${{{CACHE_DIR_VAR}:-}}""#), |gen| {
+            gen.comment("Checking if we own the directory")?;
+            writeln!(gen, r#"_commaslash_dir_x="$commaslash_dir""#)?;
+            writeln!(gen, r#"while ! test -e "$_commaslash_dir_x"; do"#)?;
+            writeln!(
+                gen,
+                r#"    _commaslash_dir_x="$(dirname "$_commaslash_dir_x")""#
+            )?;
+            writeln!(gen, r#"done"#)?;
+            gen.comment("If we don't own the directory, we will cache in a temporary directory")?;
+            writeln!(
+                gen,
+                r#"if test "$({})" -ne "$({})"; then"#,
+                file_owner_command(r#""$_commaslash_dir_x""#),
+                euid_command()
+            )?;
+            writeln!(gen, r#"    commaslash_dir="{}""#, backup_commaslash_dir())?;
+            writeln!(gen, r#"fi"#)?;
+            Ok(())
+        })?;
 
-        writeln!(self, r#"install_dir="$commaslash_dir/{}""#, spec.sha256)?;
-        writeln!(self, r#"temp_dir="$install_dir.temp""#)?;
+        self.assign_raw(
+            "install_dir",
+            format_args!(r#""$commaslash_dir/{}""#, spec.digest.cache_name()),
+        )?;
+        self.assign_raw("temp_dir", r#""$install_dir.temp""#)?;
         // Temp dir may be left after another run (although this is very improbable).
         writeln!(self, r#"rm -rf "$temp_dir""#)?;
         writeln!(self, r#"mkdir -p "$temp_dir""#)?;
-        writeln!(self, r#"trap "rm -rf $temp_dir" EXIT"#)?;
+        writeln!(self, r#"trap 'rm -rf "$temp_dir"' EXIT"#)?;
         writeln!(self, r#"mkdir -p "$(dirname "$install_dir")""#)?;
-        writeln!(self, r#"exe="$install_dir/{}""#, spec.path)?;
+        self.comment("The fast path touches use markers not newer than the stamp.")?;
+        self.if_fi(r#"! test -e "$commaslash_dir/used.stamp""#, |gen| {
+            writeln!(
+                gen,
+                r#"touch "$commaslash_dir/used.stamp" 2>/dev/null || true"#
+            )?;
+            Ok(())
+        })?;
+        self.assign_raw(
+            "exe",
+            format_args!(r#""$install_dir/{}""#, ShDqEscape(spec.path.as_str())),
+        )?;
         self.subprocess(|gen| {
             writeln!(gen, r#"exec 9>"$install_dir.lock""#)?;
             gen.comment("It is OK to fail to aquire lock,")?;
             gen.comment("Because we generate unique names and rename atomically.")?;
             gen.comment("If we fail to aquire lock, we will download twice, but we won't corrupt.")?;
             writeln!(gen, "{} || true", target_platform.os().flock().lock_fs(120, 9))?;
-            gen.if_fi(r#"test -x "$exe""#, |gen| {
-                // Another process has just prepared the directory.
-                writeln!(gen, r#"exit 0"#)?;
-                Ok(())
-            })?;
-            writeln!(gen, r#"curl --location --retry 3 --fail --silent --show-error --output "$temp_dir/zip" {}"#,
-                shlex::try_quote(&spec.url)?
+            gen.if_fi(
+                &format!(
+                    r#"test {} "$exe" && {{ test "${{{VERIFY_VAR}:-}}" != 1 || _commaslash_verify "$install_dir"; }}"#,
+                    exe_test(spec)
+                ),
+                |gen| {
+                    // Another process has just prepared the directory.
+                    writeln!(gen, r#"exit 0"#)?;
+                    Ok(())
+                },
             )?;
-            writeln!(gen, "{} >/dev/null", sha256_command.check_command(spec.sha256, "$temp_dir/zip"))?;
-            writeln!(gen, r#"unzip -qq "$temp_dir/zip" -d "$temp_dir/{}" >/dev/null"#, spec.sha256)?;
-            writeln!(gen, r#"if ! test -x "$temp_dir/{}/{}"; then"#, spec.sha256, spec.path)?;
-            writeln!(gen, r#"   echo "unzipped dir $temp_dir/{} does not have executable file {}" >&2; exit 1"#, spec.sha256, spec.path)?;
+            gen.obtain_archive(spec)?;
+            writeln!(gen, "{} >/dev/null", digest_command.check_command(spec.digest, "$temp_dir/zip"))?;
+            gen.store_archive(spec)?;
+            writeln!(gen, "{}", spec.archive_format.extract_command(r#""$temp_dir/zip""#, &format!(r#""$temp_dir/{}""#, spec.digest.cache_name())))?;
+            if spec.strip_components > 0 {
+                gen.strip_components(spec)?;
+            }
+            gen.post_extract(spec)?;
+            writeln!(gen, r#"if ! test {} "$temp_dir/{}/{}"; then"#, exe_test(spec), spec.digest.cache_name(), ShDqEscape(spec.path.as_str()))?;
+            let file = if spec.interpreter.is_some() { "file" } else { "executable file" };
+            writeln!(gen, r#"   echo "unpacked dir $temp_dir/{} does not have {file} {}" >&2; exit 1"#, spec.digest.cache_name(), ShDqEscape(spec.path.as_str()))?;
             writeln!(gen, r#"fi"#)?;
-            writeln!(gen, r#"mv "$temp_dir/{}" "{}""#, spec.sha256, commaslash_dir(&target_platform.os()))?;
+            writeln!(gen, r#"_commaslash_manifest "$temp_dir/{}" >"$temp_dir/manifest""#, spec.digest.cache_name())?;
+            gen.comment("Install which failed verification")?;
+            gen.if_fi(r#"test -e "$install_dir""#, |gen| {
+                writeln!(gen, r#"mv "$install_dir" "$temp_dir/old""#)?;
+                Ok(())
+            })?;
+            writeln!(gen, r#"mv "$temp_dir/{}" "$commaslash_dir""#, spec.digest.cache_name())?;
+            writeln!(gen, r#"mv "$temp_dir/manifest" "$install_dir.manifest""#)?;
             Ok(())
         })?;
         self.comment("We have set up `trap` above, but `trap` is not executed on `exec`")?;
         writeln!(self, r#"rm -rf "$temp_dir""#)?;
-        writeln!(self, r#"exec "$exe" "$@""#)?;
+        writeln!(self, "_commaslash_installed=1")?;
+        let (env, command) = exec_env_and_command("$install_dir", "$exe", spec);
+        writeln!(
+            self,
+            "{}",
+            exec_command("$install_dir", "$exe", &env, &command)
+        )?;
         Ok(())
     }
 
@@ -240,10 +629,10 @@ impl Gen {
         writeln!(self, r#"set -e"#)?;
         writeln!(self, r#"case "$(uname -sm)" in"#)?;
         self.indented(|gen| {
-            for (target_platform, spec) in &spec.specs {
-                writeln!(gen, r#""{}")"#, target_platform.uname_sm())?;
+            for (target_platforms, spec) in spec_groups(spec) {
+                writeln!(gen, "{})", case_pattern(&target_platforms))?;
                 gen.indented(|gen| {
-                    gen.slow_path_for_target(&target_platform, spec)?;
+                    gen.slow_path_for_target(&target_platforms[0], spec)?;
                     writeln!(gen, ";;")?;
                     Ok(())
                 })?;
@@ -260,8 +649,22 @@ impl Gen {
     }
 
     fn gen(mut self, spec: &ResolvedSpec) -> anyhow::Result<String> {
-        writeln!(self, "#!/bin/sh")?;
-        writeln!(self, "# {}generated by commaslash", "@")?;
+        self.write_str(HEADER)?;
+        for (target_platform, spec) in &spec.specs {
+            writeln!(self, "{SPEC_PREFIX}{target_platform} {spec}")?;
+        }
+        if let Some(cache_dir_expr) = &self.options.cache_dir_expr {
+            writeln!(self, "{CACHE_DIR_EXPR_PREFIX}{cache_dir_expr}")?;
+        }
+        if let Some(version) = &self.options.version {
+            writeln!(self, "{VERSION_PREFIX}{version}")?;
+        }
+        for (target_platform, source) in &self.options.sources {
+            writeln!(self, "{SOURCE_PREFIX}{target_platform} {source}")?;
+        }
+
+        self.define_verify()?;
+        self.define_exec()?;
 
         self.fast_path(spec)?;
 
@@ -273,10 +676,521 @@ impl Gen {
     }
 }
 
-pub(crate) fn gen(spec: &ResolvedSpec) -> anyhow::Result<String> {
+/// Platforms grouped by identical specs, e.g. both macOS arches of a universal binary,
+/// so each group gets one `case` arm.
+fn spec_groups(spec: &ResolvedSpec) -> Vec<(Vec<TargetPlatform>, &ResolvedTargetSpec)> {
+    let mut groups: Vec<(Vec<TargetPlatform>, &ResolvedTargetSpec)> = Vec::new();
+    for (target_platform, target_spec) in &spec.specs {
+        // Platforms of different OSes differ in cache dir and commands.
+        let group = groups
+            .iter_mut()
+            .find(|(platforms, s)| platforms[0].os() == target_platform.os() && *s == target_spec);
+        match group {
+            Some((platforms, _)) => platforms.push(target_platform),
+            None => groups.push((vec![target_platform], target_spec)),
+        }
+    }
+    groups
+}
+
+/// `case` pattern matching `uname -sm` of any of `target_platforms`.
+fn case_pattern(target_platforms: &[TargetPlatform]) -> String {
+    let mut patterns: Vec<String> = target_platforms
+        .iter()
+        .map(|target_platform| format!(r#""{}""#, target_platform.uname_sm()))
+        .collect();
+    patterns.sort();
+    patterns.join("|")
+}
+
+/// Shell functions which compute and check the install manifest.
+#[cfg(test)]
+pub(crate) fn verify_functions() -> String {
+    let options = GenOptions::default();
+    let mut gen = Gen {
+        script: String::new(),
+        indent: 0,
+        options: &options,
+    };
+    gen.define_verify().unwrap();
+    gen.script
+}
+
+pub(crate) fn gen(spec: &ResolvedSpec, options: &GenOptions) -> anyhow::Result<String> {
+    options.validate()?;
     Gen {
         script: String::new(),
         indent: 0,
+        options,
     }
     .gen(spec)
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::digest::{Digest, DigestAlgorithm};
+    use crate::gen::{gen, GenOptions};
+    use crate::manifest::manifest;
+    use crate::spec::resolve::{ResolveOptions, ResolvedSpec};
+    use crate::target_platform::TargetPlatform;
+    use crate::testutil::{
+        run_script, script_command, write_spec_script, write_tar, write_test_script,
+        write_test_script_with_digest, write_zip,
+    };
+    use ordinal_map::map::OrdinalMap;
+    use proptest::prelude::*;
+    use std::fs;
+    use std::path::Path;
+    use std::time::{Duration, SystemTime};
+    use tempfile::TempDir;
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    const INJECTIONS: &[&str] = &[
+        "$(touch pwned)",
+        "`touch pwned`",
+        "\";touch pwned;\"",
+        "';touch pwned;'",
+        "$(touch${IFS}pwned)",
+    ];
+
+    fn path_component() -> impl Strategy<Value = String> {
+        prop_oneof![
+            "[a-zA-Z0-9._ $`\"';|&()<>*?!{}~#=-]{1,6}"
+                .prop_filter("not a relative component", |c| c != "." && c != ".."),
+            proptest::sample::select(INJECTIONS).prop_map(str::to_owned),
+        ]
+    }
+
+    fn url_component() -> impl Strategy<Value = String> {
+        prop_oneof![
+            // `?`, `#` and `%` have special meaning in URLs.
+            "[a-zA-Z0-9._$`\"';|&()<>*!{}~=-]{1,6}",
+            Just("$(touch${IFS}pwned)".to_owned()),
+        ]
+    }
+
+    #[test]
+    fn test_print_path() {
+        let dir = TempDir::new().unwrap();
+        let script = write_test_script(
+            dir.path(),
+            &dir.path().join("archive"),
+            "bin/tool",
+            &GenOptions::default(),
+        );
+        let print_path = [("COMMASLASH_PRINT_PATH", "1")];
+
+        // Slow path.
+        let exe = run_script(&script, dir.path(), &print_path);
+        let exe = Path::new(exe.strip_suffix('\n').unwrap());
+        assert!(exe.is_absolute());
+        assert!(exe.starts_with(dir.path().join("cache")));
+        assert!(exe.ends_with("bin/tool"));
+        assert_eq!("ok\n", run_script(exe, dir.path(), &[]));
+
+        // Fast path.
+        assert_eq!(
+            format!("{}\n", exe.display()),
+            run_script(&script, dir.path(), &print_path)
+        );
+        assert_eq!("ok\n", run_script(&script, dir.path(), &[]));
+
+        // Relative cache dir.
+        assert_eq!(
+            format!("{}\n", exe.canonicalize().unwrap().display()),
+            run_script(
+                &script,
+                dir.path(),
+                &[("COMMASLASH_PRINT_PATH", "1"), ("XDG_CACHE_HOME", "cache")]
+            )
+        );
+    }
+
+    #[test]
+    fn test_used_marker() {
+        let dir = TempDir::new().unwrap();
+        let script = write_test_script(
+            dir.path(),
+            &dir.path().join("archive"),
+            "bin/tool",
+            &GenOptions::default(),
+        );
+        let exe = run_script(&script, dir.path(), &[("COMMASLASH_PRINT_PATH", "1")]);
+        let install_dir = Path::new(exe.trim_end())
+            .parent()
+            .unwrap()
+            .parent()
+            .unwrap();
+        let used = install_dir.with_extension("used");
+        let stamp = install_dir.with_file_name("used.stamp");
+        let modified = |path: &Path| fs::metadata(path).unwrap().modified().unwrap();
+        let set_modified = |path: &Path, time: SystemTime| {
+            fs::File::options()
+                .write(true)
+                .open(path)
+                .unwrap()
+                .set_modified(time)
+                .unwrap()
+        };
+
+        assert!(modified(&used) > SystemTime::now() - DAY / 2);
+        assert!(stamp.exists());
+
+        // Not updated again until the next gc.
+        set_modified(&stamp, SystemTime::now() - DAY);
+        let recent = SystemTime::now() - DAY / 2;
+        set_modified(&used, recent);
+        assert_eq!("ok\n", run_script(&script, dir.path(), &[]));
+        assert_eq!(recent, modified(&used));
+
+        // Updated once the marker is not newer than the stamp of a gc.
+        set_modified(&stamp, recent);
+        assert_eq!("ok\n", run_script(&script, dir.path(), &[]));
+        assert!(modified(&used) > recent + DAY / 4);
+    }
+
+    #[test]
+    fn test_verify() {
+        let dir = TempDir::new().unwrap();
+        let script = write_test_script(
+            dir.path(),
+            &dir.path().join("archive"),
+            "bin/tool",
+            &GenOptions::default(),
+        );
+        let exe = run_script(&script, dir.path(), &[("COMMASLASH_PRINT_PATH", "1")]);
+        let exe = Path::new(exe.trim_end());
+        let install_dir = exe.parent().unwrap().parent().unwrap();
+        assert_eq!(
+            manifest(install_dir).unwrap(),
+            fs::read(install_dir.with_extension("manifest")).unwrap()
+        );
+
+        fs::write(exe, "#!/bin/sh\necho tampered\n").unwrap();
+        assert_eq!("tampered\n", run_script(&script, dir.path(), &[]));
+        let verify = [("COMMASLASH_VERIFY", "1")];
+        assert_eq!("ok\n", run_script(&script, dir.path(), &verify));
+        assert_eq!("ok\n", run_script(&script, dir.path(), &[]));
+
+        // Missing manifest cannot be verified either.
+        fs::write(exe, "#!/bin/sh\necho tampered\n").unwrap();
+        fs::remove_file(install_dir.with_extension("manifest")).unwrap();
+        assert_eq!("ok\n", run_script(&script, dir.path(), &verify));
+        assert!(install_dir.with_extension("manifest").exists());
+    }
+
+    #[test]
+    fn test_fetch_only() {
+        let dir = TempDir::new().unwrap();
+        let script = write_test_script(
+            dir.path(),
+            &dir.path().join("archive"),
+            "bin/tool",
+            &GenOptions::default(),
+        );
+        let fetch_only = [("COMMASLASH_FETCH_ONLY", "1")];
+
+        // Slow path.
+        assert_eq!("", run_script(&script, dir.path(), &fetch_only));
+        // Fast path.
+        assert_eq!("", run_script(&script, dir.path(), &fetch_only));
+        assert_eq!("ok\n", run_script(&script, dir.path(), &[]));
+    }
+
+    #[test]
+    fn test_cache_dir_var() {
+        let dir = TempDir::new().unwrap();
+        let script = write_test_script(
+            dir.path(),
+            &dir.path().join("archive"),
+            "bin/tool",
+            &GenOptions::default(),
+        );
+        let cache_dir = dir.path().join("custom-cache");
+        let env = [
+            ("COMMASLASH_CACHE_DIR", cache_dir.to_str().unwrap()),
+            ("COMMASLASH_PRINT_PATH", "1"),
+        ];
+
+        // Slow path.
+        let exe = run_script(&script, dir.path(), &env);
+        assert!(Path::new(&exe).starts_with(&cache_dir));
+        // Fast path.
+        assert_eq!(exe, run_script(&script, dir.path(), &env));
+        assert!(!dir.path().join("cache").exists());
+    }
+
+    #[test]
+    fn test_cache_dir_expr() {
+        let dir = TempDir::new().unwrap();
+        let options = GenOptions {
+            cache_dir_expr: Some("$HOME/ci-cache".to_owned()),
+            ..GenOptions::default()
+        };
+        let script = write_test_script(
+            dir.path(),
+            &dir.path().join("archive"),
+            "bin/tool",
+            &options,
+        );
+        let print_path = [("COMMASLASH_PRINT_PATH", "1")];
+
+        let exe = run_script(&script, dir.path(), &print_path);
+        assert!(Path::new(&exe).starts_with(dir.path().join("home/ci-cache/commaslash")));
+        assert_eq!(exe, run_script(&script, dir.path(), &print_path));
+        assert!(!dir.path().join("cache").exists());
+    }
+
+    #[test]
+    fn test_offline() {
+        let dir = TempDir::new().unwrap();
+        let archive_dir = dir.path().join("archive");
+        let script =
+            write_test_script(dir.path(), &archive_dir, "bin/tool", &GenOptions::default());
+        let archive = archive_dir.join("archive.zip");
+        let digest = Digest::of_file(DigestAlgorithm::Sha256, &archive).unwrap();
+
+        let output = script_command(&script, dir.path())
+            .env("COMMASLASH_OFFLINE", "1")
+            .output()
+            .unwrap();
+        assert!(!output.status.success());
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains(&format!("file://{}", archive.display())));
+        assert!(stderr.contains(&digest.to_string()));
+        assert!(stderr.contains(&format!(
+            "cache/commaslash/{}/bin/tool",
+            digest.cache_name()
+        )));
+
+        let seed_dir = dir.path().join("seed");
+        fs::create_dir(&seed_dir).unwrap();
+        fs::rename(&archive, seed_dir.join(digest.cache_name())).unwrap();
+        let env = [
+            ("COMMASLASH_OFFLINE", "1"),
+            ("COMMASLASH_SEED_DIR", seed_dir.to_str().unwrap()),
+        ];
+        assert_eq!("ok\n", run_script(&script, dir.path(), &env));
+    }
+
+    #[test]
+    fn test_keep_archives() {
+        let dir = TempDir::new().unwrap();
+        let archive_dir = dir.path().join("archive");
+        let script =
+            write_test_script(dir.path(), &archive_dir, "bin/tool", &GenOptions::default());
+        let digest =
+            Digest::of_file(DigestAlgorithm::Sha256, &archive_dir.join("archive.zip")).unwrap();
+        let commaslash_dir = dir.path().join("cache/commaslash");
+
+        assert_eq!(
+            "ok\n",
+            run_script(&script, dir.path(), &[("COMMASLASH_KEEP_ARCHIVES", "1")])
+        );
+        let stored = commaslash_dir.join("archives").join(digest.cache_name());
+        assert_eq!(
+            digest,
+            Digest::of_file(DigestAlgorithm::Sha256, &stored).unwrap()
+        );
+
+        // Reinstall without the original archive.
+        fs::remove_dir_all(&archive_dir).unwrap();
+        fs::remove_dir_all(commaslash_dir.join(digest.cache_name())).unwrap();
+        assert_eq!("ok\n", run_script(&script, dir.path(), &[]));
+    }
+
+    #[test]
+    fn test_sha512() {
+        let dir = TempDir::new().unwrap();
+        let archive_dir = dir.path().join("archive");
+        let script = write_test_script_with_digest(
+            dir.path(),
+            &archive_dir,
+            "bin/tool",
+            &GenOptions::default(),
+            DigestAlgorithm::Sha512,
+        );
+        let digest =
+            Digest::of_file(DigestAlgorithm::Sha512, &archive_dir.join("archive.zip")).unwrap();
+
+        assert_eq!("ok\n", run_script(&script, dir.path(), &[]));
+        assert!(dir
+            .path()
+            .join("cache/commaslash")
+            .join(format!("sha512-{}", digest.hex()))
+            .join("bin/tool")
+            .exists());
+    }
+
+    #[test]
+    fn test_strip_components() {
+        for extension in ["zip", "tar.gz", "tar.xz"] {
+            strip_components(extension);
+        }
+    }
+
+    fn strip_components(extension: &str) {
+        let dir = TempDir::new().unwrap();
+        let archive = dir.path().join(format!("tool-1.0.{extension}"));
+        let entries: &[(&str, &[u8], u32)] =
+            &[("tool-1.0/bin/tool", b"#!/bin/sh\necho ok\n", 0o755)];
+        match extension {
+            "zip" => write_zip(&archive, entries),
+            _ => write_tar(&archive, entries),
+        }
+        let script = dir.path().join("tool");
+        write_spec_script(
+            &script,
+            &format!(
+                "url=file://{} path=bin/tool strip_components=1",
+                archive.display()
+            ),
+            &GenOptions::default(),
+        );
+
+        let exe = run_script(&script, dir.path(), &[("COMMASLASH_PRINT_PATH", "1")]);
+        let exe = Path::new(exe.trim_end());
+        let install_dir = exe.parent().unwrap().parent().unwrap();
+        assert!(install_dir.join("bin/tool").exists());
+        assert_eq!(
+            manifest(install_dir).unwrap(),
+            fs::read(install_dir.with_extension("manifest")).unwrap()
+        );
+        assert_eq!("ok\n", run_script(&script, dir.path(), &[]));
+    }
+
+    #[test]
+    fn test_args_env() {
+        let dir = TempDir::new().unwrap();
+        let archive = dir.path().join("tool.zip");
+        write_zip(
+            &archive,
+            &[(
+                "bin/tool",
+                b"#!/bin/sh\nprintf '%s|' \"$TOOL_HOME\" \"$@\"\n",
+                0o755,
+            )],
+        );
+        let script = dir.path().join("tool");
+        let spec = format!(
+            "url=file://{} path=bin/tool arg=-I{{install_dir}}/include arg=$(touch${{IFS}}pwned) \
+             env=TOOL_HOME={{install_dir}}/`touch${{IFS}}pwned`",
+            archive.display()
+        );
+        write_spec_script(&script, &spec, &GenOptions::default());
+
+        let exe = run_script(&script, dir.path(), &[("COMMASLASH_PRINT_PATH", "1")]);
+        let install_dir = Path::new(exe.trim_end())
+            .parent()
+            .unwrap()
+            .parent()
+            .unwrap();
+        let expected = format!(
+            "{0}/`touch${{IFS}}pwned`|-I{0}/include|$(touch${{IFS}}pwned)|a b|",
+            install_dir.display()
+        );
+        // Slow path.
+        fs::remove_dir_all(install_dir).unwrap();
+        let output = script_command(&script, dir.path())
+            .arg("a b")
+            .output()
+            .unwrap();
+        assert_eq!(expected, String::from_utf8(output.stdout).unwrap());
+        // Fast path.
+        let output = script_command(&script, dir.path())
+            .arg("a b")
+            .output()
+            .unwrap();
+        assert_eq!(expected, String::from_utf8(output.stdout).unwrap());
+        assert!(!dir.path().join("pwned").exists());
+    }
+
+    #[test]
+    fn test_identical_specs_share_case_arm() {
+        let dir = TempDir::new().unwrap();
+        let archive = dir.path().join("tool.zip");
+        write_zip(&archive, &[("bin/tool", b"#!/bin/sh\necho ok\n", 0o755)]);
+        let source = format!("url=file://{} path=bin/tool", archive.display());
+        let sources = OrdinalMap::from_iter([
+            (TargetPlatform::LinuxX86_64, source.clone()),
+            (TargetPlatform::MacosX86_64, source.clone()),
+            (TargetPlatform::MacosAarch64, source),
+        ]);
+        let spec = ResolvedSpec::resolve_sources(&sources, None, &ResolveOptions::default())
+            .unwrap()
+            .remove(0);
+        let script_text = gen(&spec, &GenOptions::default()).unwrap();
+        let arms = |pattern: &str| {
+            script_text
+                .lines()
+                .filter(|line| line.trim_start() == pattern)
+                .count()
+        };
+        // Fast and slow path.
+        assert_eq!(2, arms(r#""Darwin arm64"|"Darwin x86_64")"#));
+        assert_eq!(2, arms(r#""Linux x86_64")"#));
+        assert_eq!(0, arms(r#""Darwin arm64")"#));
+
+        let script = dir.path().join("tool");
+        fs::write(&script, script_text).unwrap();
+        assert_eq!("ok\n", run_script(&script, dir.path(), &[]));
+        assert_eq!("ok\n", run_script(&script, dir.path(), &[]));
+    }
+
+    #[test]
+    fn test_interpreter() {
+        let dir = TempDir::new().unwrap();
+        let archive = dir.path().join("tool.zip");
+        write_zip(
+            &archive,
+            &[("lib/tool.sh", b"printf '%s|' \"$@\"\n", 0o644)],
+        );
+        let write_script = |interpreter: &str| {
+            let script = dir.path().join(interpreter);
+            let spec = format!(
+                "url=file://{} path=lib/tool.sh interpreter={interpreter} interpreter_arg=-e arg=x",
+                archive.display()
+            );
+            write_spec_script(&script, &spec, &GenOptions::default());
+            script
+        };
+
+        let script = write_script("sh");
+        // Slow path.
+        assert_eq!("x|", run_script(&script, dir.path(), &[]));
+        // Fast path.
+        let output = script_command(&script, dir.path())
+            .arg("a b")
+            .output()
+            .unwrap();
+        assert_eq!("x|a b|", String::from_utf8(output.stdout).unwrap());
+
+        let script = write_script("commaslash-no-such-interpreter");
+        let output = script_command(&script, dir.path()).output().unwrap();
+        assert!(!output.status.success());
+        assert!(String::from_utf8(output.stderr)
+            .unwrap()
+            .contains("command `commaslash-no-such-interpreter` not found"));
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(32))]
+
+        #[test]
+        fn test_no_injection(
+            path in proptest::collection::vec(path_component(), 1..4),
+            url_dir in url_component(),
+        ) {
+            let path = path.join("/");
+            let dir = TempDir::new().unwrap();
+            let script = write_test_script(dir.path(), &dir.path().join(&url_dir), &path, &GenOptions::default());
+
+            // Slow path.
+            prop_assert_eq!("ok\n", run_script(&script, dir.path(), &[]));
+            // Fast path.
+            prop_assert_eq!("ok\n", run_script(&script, dir.path(), &[]));
+            prop_assert!(!dir.path().join("pwned").exists());
+        }
+    }
+}
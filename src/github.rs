@@ -1,6 +1,297 @@
+//! GitHub Releases, used to resolve `github=<owner>/<repo> tag=<tag> asset=<pattern>` specs.
+
+use crate::download::download;
+use crate::os::Os;
+use crate::target_platform::TargetPlatform;
+use anyhow::Context;
+#[cfg(test)]
 use std::env;
 
+pub(crate) const DEFAULT_API_URL: &str = "https://api.github.com";
+
+#[derive(serde::Deserialize)]
+pub(crate) struct ReleaseAsset {
+    pub(crate) name: String,
+    pub(crate) size: u64,
+    pub(crate) browser_download_url: String,
+    /// Like `sha256:<hex>`, only present for assets uploaded recently.
+    #[serde(default)]
+    pub(crate) digest: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct Release {
+    tag_name: String,
+    assets: Vec<ReleaseAsset>,
+}
+
+/// Download url of a release asset, known without querying the API.
+pub(crate) fn release_download_url(repo: &str, tag: &str, name: &str) -> String {
+    format!("https://github.com/{repo}/releases/download/{tag}/{name}")
+}
+
+/// List assets of the release `tag` of `repo` (`<owner>/<repo>`).
+pub(crate) fn release_assets(
+    api_url: &str,
+    repo: &str,
+    tag: &str,
+) -> anyhow::Result<Vec<ReleaseAsset>> {
+    let url = format!(
+        "{}/repos/{repo}/releases/tags/{tag}",
+        api_url.trim_end_matches('/')
+    );
+    let release = download(&url)?;
+    let release: Release = serde_json::from_slice(&release)
+        .with_context(|| format!("Failed to parse release from `{url}`"))?;
+    Ok(release.assets)
+}
+
+/// Tag of the latest release of `repo`.
+pub(crate) fn latest_release_tag(api_url: &str, repo: &str) -> anyhow::Result<String> {
+    let url = format!(
+        "{}/repos/{repo}/releases/latest",
+        api_url.trim_end_matches('/')
+    );
+    let release = download(&url)?;
+    let release: Release = serde_json::from_slice(&release)
+        .with_context(|| format!("Failed to parse release from `{url}`"))?;
+    Ok(release.tag_name)
+}
+
+/// Pattern has placeholders, so the asset name is only known after listing assets.
+pub(crate) fn has_placeholders(pattern: &str) -> bool {
+    pattern.contains('{')
+}
+
+/// Alternatives for each part of the pattern; literal parts have one alternative.
+fn expand_pattern<'a>(
+    pattern: &'a str,
+    target_platform: TargetPlatform,
+    tag: &'a str,
+) -> anyhow::Result<Vec<Vec<&'a str>>> {
+    let mut parts = Vec::new();
+    let mut rest = pattern;
+    while let Some((literal, placeholder)) = rest.split_once('{') {
+        let (placeholder, after) = placeholder
+            .split_once('}')
+            .with_context(|| format!("Unclosed `{{` in asset pattern `{pattern}`"))?;
+        parts.push(vec![literal]);
+        parts.push(match placeholder {
+            "os" => os_names(target_platform).to_vec(),
+            "arch" => arch_names(target_platform).to_vec(),
+            "version" => vec![tag.strip_prefix('v').unwrap_or(tag)],
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "Unknown placeholder `{{{placeholder}}}` in asset pattern `{pattern}`"
+                ))
+            }
+        });
+        rest = after;
+    }
+    parts.push(vec![rest]);
+    Ok(parts)
+}
+
+/// Spellings of the OS in asset names.
+fn os_names(target_platform: TargetPlatform) -> &'static [&'static str] {
+    match target_platform.os() {
+        Os::Linux => &["linux", "Linux", "unknown-linux-gnu", "unknown-linux-musl"],
+        Os::Macos => &["macos", "darwin", "osx", "apple-darwin", "Darwin", "macOS"],
+    }
+}
+
+/// Spellings of the architecture in asset names.
+fn arch_names(target_platform: TargetPlatform) -> &'static [&'static str] {
+    match target_platform {
+        TargetPlatform::LinuxX86_64 | TargetPlatform::MacosX86_64 => {
+            &["x86_64", "amd64", "x64", "x86-64"]
+        }
+        TargetPlatform::MacosAarch64 => &["aarch64", "arm64", "aarch_64"],
+    }
+}
+
+fn matches(parts: &[Vec<&str>], name: &str) -> bool {
+    let Some((first, rest)) = parts.split_first() else {
+        return name.is_empty();
+    };
+    first.iter().any(|alt| {
+        name.strip_prefix(alt)
+            .is_some_and(|name| matches(rest, name))
+    })
+}
+
+/// Find the only asset matching the pattern for the platform.
+pub(crate) fn pick_asset(
+    assets: Vec<ReleaseAsset>,
+    pattern: &str,
+    target_platform: TargetPlatform,
+    tag: &str,
+) -> anyhow::Result<ReleaseAsset> {
+    let parts = expand_pattern(pattern, target_platform, tag)?;
+    let names: Vec<String> = assets.iter().map(|a| a.name.clone()).collect();
+    let mut matching: Vec<ReleaseAsset> = assets
+        .into_iter()
+        .filter(|a| matches(&parts, &a.name))
+        .collect();
+    match matching.len() {
+        1 => Ok(matching.pop().unwrap()),
+        0 => Err(anyhow::anyhow!(
+            "No asset matches `{pattern}` for {target_platform}; assets: {}",
+            names.join(", ")
+        )),
+        _ => Err(anyhow::anyhow!(
+            "Several assets match `{pattern}` for {target_platform}: {}",
+            matching
+                .iter()
+                .map(|a| a.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+}
+
 #[cfg(test)]
 pub(crate) fn is_github_actions() -> bool {
     env::var("GITHUB_ACTIONS").is_ok()
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::digest::{Digest, DigestAlgorithm};
+    use crate::github::{pick_asset, ReleaseAsset};
+    use crate::spec::resolve::{ResolveOptions, ResolvedTargetSpec};
+    use crate::target_platform::TargetPlatform;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn assets(names: &[&str]) -> Vec<ReleaseAsset> {
+        names
+            .iter()
+            .map(|name| ReleaseAsset {
+                name: name.to_string(),
+                size: 1,
+                browser_download_url: format!("https://example.com/{name}"),
+                digest: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_pick_asset() {
+        let names = [
+            "protoc-29.3-linux-x86_64.zip",
+            "protoc-29.3-linux-aarch_64.zip",
+            "protoc-29.3-osx-aarch_64.zip",
+            "protoc-29.3-osx-x86_64.zip",
+            "protoc-29.3-osx-universal_binary.zip",
+        ];
+        let pick = |pattern: &str, target_platform| {
+            pick_asset(assets(&names), pattern, target_platform, "v29.3").map(|a| a.name)
+        };
+        let pattern = "protoc-{version}-{os}-{arch}.zip";
+        assert_eq!(
+            "protoc-29.3-linux-x86_64.zip",
+            pick(pattern, TargetPlatform::LinuxX86_64).unwrap()
+        );
+        assert_eq!(
+            "protoc-29.3-osx-aarch_64.zip",
+            pick(pattern, TargetPlatform::MacosAarch64).unwrap()
+        );
+        assert_eq!(
+            "protoc-29.3-osx-x86_64.zip",
+            pick(pattern, TargetPlatform::MacosX86_64).unwrap()
+        );
+        assert!(pick("protoc-{version}-{os}-{arch}", TargetPlatform::LinuxX86_64).is_err());
+        assert!(pick(
+            "protoc-{version}-{platform}.zip",
+            TargetPlatform::LinuxX86_64
+        )
+        .is_err());
+
+        let names = ["tool-linux-amd64.tar.gz", "tool-Linux-x86_64.tar.gz"];
+        assert!(pick_asset(
+            assets(&names),
+            "tool-{os}-{arch}.tar.gz",
+            TargetPlatform::LinuxX86_64,
+            "v1"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_resolve_github() {
+        let dir = TempDir::new().unwrap();
+        let archive = dir.path().join("tool-1.0-darwin-arm64.zip");
+        fs::write(&archive, "archive").unwrap();
+        let digest = Digest::of_bytes(DigestAlgorithm::Sha256, b"archive");
+        let linux_archive = dir.path().join("tool-1.0-linux-amd64.zip");
+        fs::write(&linux_archive, "archive2").unwrap();
+        let release = dir.path().join("repos/owner/tool/releases/tags/v1.0");
+        fs::create_dir_all(release.parent().unwrap()).unwrap();
+        fs::write(
+            &release,
+            format!(
+                r#"{{"tag_name": "v1.0", "assets": [
+                    {{"name": "tool-1.0-darwin-arm64.zip", "size": 7,
+                      "browser_download_url": "file://{}", "digest": "{digest}"}},
+                    {{"name": "tool-1.0-linux-amd64.zip", "size": 8,
+                      "browser_download_url": "file://{}"}}
+                ]}}"#,
+                archive.display(),
+                linux_archive.display()
+            ),
+        )
+        .unwrap();
+        let options = ResolveOptions {
+            github_api_url: format!("file://{}", dir.path().display()),
+        };
+        let spec = "github=owner/tool tag=v1.0 asset=tool-{version}-{os}-{arch}.zip path=tool";
+
+        let resolved =
+            ResolvedTargetSpec::parse_and_resolve(spec, TargetPlatform::MacosAarch64, &options)
+                .unwrap();
+        assert_eq!(format!("file://{}", archive.display()), resolved.url);
+        assert_eq!(7, resolved.size);
+        assert_eq!(digest, resolved.digest);
+
+        // Listed asset without digest is downloaded to pin its digest.
+        let resolved =
+            ResolvedTargetSpec::parse_and_resolve(spec, TargetPlatform::LinuxX86_64, &options)
+                .unwrap();
+        assert_eq!(format!("file://{}", linux_archive.display()), resolved.url);
+        assert_eq!(8, resolved.size);
+        assert_eq!(
+            Digest::of_bytes(DigestAlgorithm::Sha256, b"archive2"),
+            resolved.digest
+        );
+        // Listed size is checked against the download.
+        fs::write(&linux_archive, "archive22").unwrap();
+        let error =
+            ResolvedTargetSpec::parse_and_resolve(spec, TargetPlatform::LinuxX86_64, &options)
+                .err()
+                .unwrap();
+        assert!(format!("{error:#}").contains("size mismatch"), "{error:#}");
+        // No asset for the platform.
+        assert!(
+            ResolvedTargetSpec::parse_and_resolve(spec, TargetPlatform::MacosX86_64, &options)
+                .is_err()
+        );
+
+        // Everything is known, so the API is not queried.
+        let resolved = ResolvedTargetSpec::parse_and_resolve(
+            &format!(
+                "github=owner/tool tag=v1.0 asset=tool.zip path=tool size=7 sha256={}",
+                digest.hex()
+            ),
+            TargetPlatform::LinuxX86_64,
+            &ResolveOptions {
+                github_api_url: "file:///nonexistent".to_owned(),
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            "https://github.com/owner/tool/releases/download/v1.0/tool.zip",
+            resolved.url
+        );
+    }
+}
@@ -0,0 +1,334 @@
+//! Install an archive into the cache natively, without running the generated script.
+//!
+//! Mirrors `Gen::slow_path_for_target`, so installs are interchangeable.
+
+use crate::cache;
+use crate::cache::CacheDirs;
+use crate::digest::Digest;
+use crate::gen::{KEEP_ARCHIVES_VAR, OFFLINE_VAR, SEED_DIR_VAR, VERIFY_VAR};
+use crate::manifest;
+use crate::os::env_var_non_empty;
+use crate::rel_path::RelPath;
+use crate::spec::post_extract::PostExtract;
+use crate::spec::resolve::ResolvedTargetSpec;
+use anyhow::Context;
+use std::fs;
+use std::fs::File;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Environment variables which affect installation in generated scripts.
+pub(crate) struct InstallOptions {
+    pub(crate) seed_dir: Option<PathBuf>,
+    pub(crate) offline: bool,
+    pub(crate) keep_archives: bool,
+    /// Check installs against their manifests and reinstall on mismatch.
+    pub(crate) verify: bool,
+}
+
+impl InstallOptions {
+    pub(crate) fn from_env() -> InstallOptions {
+        let is_set = |var| env_var_non_empty(var).as_deref() == Some("1");
+        InstallOptions {
+            seed_dir: env_var_non_empty(SEED_DIR_VAR).map(PathBuf::from),
+            offline: is_set(OFFLINE_VAR),
+            keep_archives: is_set(KEEP_ARCHIVES_VAR),
+            verify: is_set(VERIFY_VAR),
+        }
+    }
+}
+
+pub(crate) fn is_executable(path: &Path) -> bool {
+    path.metadata()
+        .is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+/// Whether `exe` of `spec` is installed: a file, executable unless run by an interpreter.
+pub(crate) fn is_installed_exe(spec: &ResolvedTargetSpec, exe: &Path) -> bool {
+    match spec.interpreter {
+        Some(_) => exe.is_file(),
+        None => is_executable(exe),
+    }
+}
+
+/// Path to the executable if it is installed in `commaslash_dir`
+/// and, when `verify` is set, the install matches its manifest.
+fn installed_exe(
+    commaslash_dir: &Path,
+    spec: &ResolvedTargetSpec,
+    verify: bool,
+) -> Option<PathBuf> {
+    let install_dir = cache::install_dir(commaslash_dir, spec.digest);
+    let exe = install_dir.join(spec.path.as_str());
+    if !is_installed_exe(spec, &exe) {
+        return None;
+    }
+    if verify {
+        let manifest_path = cache::manifest_path(commaslash_dir, spec.digest);
+        if !manifest::verify(&install_dir, &manifest_path).unwrap_or(false) {
+            return None;
+        }
+    }
+    Some(exe)
+}
+
+/// Find an existing install in any of the cache dirs.
+fn find_installed(dirs: &CacheDirs, spec: &ResolvedTargetSpec, verify: bool) -> Option<PathBuf> {
+    dirs.all()
+        .into_iter()
+        .find_map(|dir| installed_exe(dir, spec, verify))
+}
+
+fn run(command: &mut Command) -> anyhow::Result<()> {
+    let status = command
+        .status()
+        .with_context(|| format!("Failed to run {command:?}"))?;
+    if !status.success() {
+        return Err(anyhow::anyhow!("Command {command:?} failed: {status}"));
+    }
+    Ok(())
+}
+
+/// Put the archive to `path` and check its size and sha256.
+///
+/// Like generated scripts, use the seed dir or the archive store if possible,
+/// and do not download in offline mode.
+pub(crate) fn obtain_archive(
+    spec: &ResolvedTargetSpec,
+    options: &InstallOptions,
+    commaslash_dir: &Path,
+    path: &Path,
+    exe: &Path,
+) -> anyhow::Result<()> {
+    let local_archive = options
+        .seed_dir
+        .iter()
+        .map(|dir| dir.join(spec.digest.cache_name()))
+        .chain([cache::archive_path(commaslash_dir, spec.digest)])
+        .find(|archive| archive.is_file());
+    if let Some(local_archive) = local_archive {
+        fs::copy(&local_archive, path)
+            .with_context(|| format!("Failed to copy `{}`", local_archive.display()))?;
+    } else if options.offline {
+        return Err(anyhow::anyhow!(
+            "{OFFLINE_VAR}=1: not downloading {} ({}) to install {}",
+            spec.url,
+            spec.digest,
+            exe.display()
+        ));
+    } else {
+        run(Command::new("curl")
+            .args(["--globoff", "--location", "--retry", "3", "--fail"])
+            .args(["--silent", "--show-error", "--output"])
+            .arg(path)
+            .arg(&spec.url))?;
+    }
+    verify_archive(spec, path)?;
+    if options.keep_archives {
+        store_archive(commaslash_dir, spec.digest, path)?;
+    }
+    Ok(())
+}
+
+/// Copy verified archive to the archive store.
+fn store_archive(commaslash_dir: &Path, digest: Digest, path: &Path) -> anyhow::Result<()> {
+    let archive = cache::archive_path(commaslash_dir, digest);
+    if archive.is_file() {
+        return Ok(());
+    }
+    let temp = archive.with_extension("temp");
+    fs::create_dir_all(cache::archives_dir(commaslash_dir))?;
+    fs::copy(path, &temp)?;
+    fs::rename(&temp, &archive)?;
+    Ok(())
+}
+
+fn verify_archive(spec: &ResolvedTargetSpec, path: &Path) -> anyhow::Result<()> {
+    let size = path.metadata()?.len();
+    if size != spec.size {
+        return Err(anyhow::anyhow!(
+            "Archive `{}` size mismatch: expected {}, got {}",
+            spec.url,
+            spec.size,
+            size
+        ));
+    }
+    let digest = Digest::of_file(spec.digest.algorithm(), path)?;
+    if digest != spec.digest {
+        return Err(anyhow::anyhow!(
+            "Archive `{}` digest mismatch: expected {}, got {}",
+            spec.url,
+            spec.digest,
+            digest
+        ));
+    }
+    Ok(())
+}
+
+/// Install the archive unless it is already installed. Returns path to the executable.
+pub(crate) fn install(
+    dirs: &CacheDirs,
+    options: &InstallOptions,
+    spec: &ResolvedTargetSpec,
+) -> anyhow::Result<PathBuf> {
+    if let Some(exe) = find_installed(dirs, spec, options.verify) {
+        return Ok(exe);
+    }
+    install_in(dirs.writable(), options, spec)
+}
+
+/// Install the archive into `commaslash_dir` unless it is already installed there.
+///
+/// With `options.verify`, an install which does not match its manifest is replaced.
+pub(crate) fn install_in(
+    commaslash_dir: &Path,
+    options: &InstallOptions,
+    spec: &ResolvedTargetSpec,
+) -> anyhow::Result<PathBuf> {
+    let temp_dir = cache::temp_dir(commaslash_dir, spec.digest);
+    let exe = cache::install_dir(commaslash_dir, spec.digest).join(spec.path.as_str());
+
+    fs::create_dir_all(commaslash_dir)
+        .with_context(|| format!("Failed to create `{}`", commaslash_dir.display()))?;
+    let stamp = cache::used_stamp_path(commaslash_dir);
+    if !stamp.exists() {
+        File::create(&stamp).with_context(|| format!("Failed to create `{}`", stamp.display()))?;
+    }
+    let lock = File::create(cache::lock_path(commaslash_dir, spec.digest))?;
+    // Same lock as generated scripts take. Like scripts, proceed even if locking fails:
+    // unique names and atomic rename protect from corruption.
+    let _ = lock.lock();
+    if let Some(exe) = installed_exe(commaslash_dir, spec, options.verify) {
+        // Another process has just prepared the directory.
+        return Ok(exe);
+    }
+
+    // Temp dir may be left after another run.
+    let _ = fs::remove_dir_all(&temp_dir);
+    fs::create_dir_all(&temp_dir)?;
+    let result = install_locked(spec, options, commaslash_dir, &temp_dir, &exe);
+    let _ = fs::remove_dir_all(&temp_dir);
+    result?;
+    Ok(exe)
+}
+
+fn install_locked(
+    spec: &ResolvedTargetSpec,
+    options: &InstallOptions,
+    commaslash_dir: &Path,
+    temp_dir: &Path,
+    exe: &Path,
+) -> anyhow::Result<()> {
+    let install_dir = cache::install_dir(commaslash_dir, spec.digest);
+    let archive = temp_dir.join("zip");
+    obtain_archive(spec, options, commaslash_dir, &archive, exe)?;
+    let unpacked = temp_dir.join(spec.digest.cache_name());
+    spec.archive_format.extract(&archive, &unpacked)?;
+    if spec.strip_components > 0 {
+        strip_components(&unpacked, spec.strip_components, &temp_dir.join("stripped"))?;
+    }
+    post_extract(&unpacked, &spec.post_extract)?;
+    if !is_installed_exe(spec, &unpacked.join(spec.path.as_str())) {
+        return Err(anyhow::anyhow!(
+            "unpacked dir `{}` does not have {} `{}`",
+            unpacked.display(),
+            if spec.interpreter.is_some() {
+                "file"
+            } else {
+                "executable file"
+            },
+            spec.path
+        ));
+    }
+    let manifest_temp = temp_dir.join("manifest");
+    manifest::write_manifest(&unpacked, &manifest_temp)?;
+    if install_dir.symlink_metadata().is_ok() {
+        // Install which failed verification.
+        fs::rename(&install_dir, temp_dir.join("old"))?;
+    }
+    fs::rename(&unpacked, &install_dir)
+        .with_context(|| format!("Failed to move install to `{}`", install_dir.display()))?;
+    fs::rename(
+        &manifest_temp,
+        cache::manifest_path(commaslash_dir, spec.digest),
+    )?;
+    Ok(())
+}
+
+/// Replace `unpacked` with the only directory `strip_components` levels deep in it.
+fn strip_components(unpacked: &Path, strip_components: u32, temp: &Path) -> anyhow::Result<()> {
+    let mut stripped = unpacked.to_owned();
+    for _ in 0..strip_components {
+        let mut entries = fs::read_dir(&stripped)?.collect::<Result<Vec<_>, _>>()?;
+        match (entries.pop(), entries.is_empty()) {
+            (Some(entry), true) if entry.file_type()?.is_dir() => stripped = entry.path(),
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "Cannot strip components: `{}` is not a single directory",
+                    stripped.display()
+                ))
+            }
+        }
+    }
+    fs::rename(&stripped, temp)?;
+    fs::remove_dir_all(unpacked)?;
+    fs::rename(temp, unpacked)?;
+    Ok(())
+}
+
+/// Apply post-extract actions to `unpacked`, like the generated script does.
+fn post_extract(unpacked: &Path, actions: &[PostExtract]) -> anyhow::Result<()> {
+    for action in actions {
+        match action {
+            PostExtract::ChmodX(path) => {
+                check_no_symlink_parent(unpacked, "chmod_x", path)?;
+                let file = unpacked.join(path.as_str());
+                let metadata = fs::symlink_metadata(&file)
+                    .with_context(|| format!("Failed to chmod `{}`", file.display()))?;
+                // Like the script, do not follow symlinks, possibly out of the install.
+                if metadata.is_symlink() {
+                    return Err(anyhow::anyhow!("cannot chmod_x `{path}`: it is a symlink"));
+                }
+                let mut permissions = metadata.permissions();
+                permissions.set_mode(permissions.mode() | 0o111);
+                fs::set_permissions(&file, permissions)?;
+            }
+            PostExtract::Symlink { link, target } => {
+                check_no_symlink_parent(unpacked, "create symlink", link)?;
+                let file = unpacked.join(link.as_str());
+                // Like the script, fail rather than link inside an existing directory.
+                if file.symlink_metadata().is_ok() {
+                    return Err(anyhow::anyhow!(
+                        "cannot create symlink `{link}`: it already exists"
+                    ));
+                }
+                std::os::unix::fs::symlink(target, &file)
+                    .with_context(|| format!("Failed to create symlink `{}`", file.display()))?;
+            }
+            PostExtract::Remove(path) => {
+                check_no_symlink_parent(unpacked, "remove", path)?;
+                let path = unpacked.join(path.as_str());
+                let result = match path.symlink_metadata() {
+                    Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(&path),
+                    Ok(_) => fs::remove_file(&path),
+                    Err(_) => Ok(()),
+                };
+                result.with_context(|| format!("Failed to remove `{}`", path.display()))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Like the script, do not follow a symlinked parent directory, possibly out of the install.
+fn check_no_symlink_parent(unpacked: &Path, action: &str, path: &RelPath) -> anyhow::Result<()> {
+    for parent in path.parents() {
+        if fs::symlink_metadata(unpacked.join(parent.as_str())).is_ok_and(|m| m.is_symlink()) {
+            return Err(anyhow::anyhow!(
+                "cannot {action} `{path}`: `{parent}` is a symlink"
+            ));
+        }
+    }
+    Ok(())
+}
@@ -1,81 +1,227 @@
 mod archive;
+mod cache;
+mod cache_cmd;
+mod checksums;
+mod digest;
+mod digest_command;
+mod download;
+mod exe_header;
+mod fetch;
+mod gc;
 mod gen;
 mod genpy;
+mod github;
+mod install;
 mod lockf;
+mod manifest;
 mod os;
 mod rel_path;
+mod script;
 mod sh;
-mod sha256;
+mod shx;
+mod signature;
+mod smoke_test;
 pub(crate) mod spec;
 mod target_platform;
-mod sha256_command;
 mod testutil;
-mod github;
-mod shx;
+mod update;
 
-use std::fs;
-use std::fs::Permissions;
-use std::os::unix::fs::PermissionsExt;
-use crate::gen::gen;
+use crate::cache_cmd::{cache_main, CacheArgs};
+use crate::fetch::{fetch, FetchArgs};
+use crate::gc::{gc, GcArgs};
+use crate::gen::{gen, GenOptions};
+use crate::github::DEFAULT_API_URL;
+use crate::smoke_test::SmokeTest;
+use crate::spec::parse::ParsedTargetSpec;
+use crate::spec::template::SpecTemplate;
 use crate::target_platform::TargetPlatform;
+use crate::update::{update, UpdateArgs};
 use anyhow::Context;
 use clap::Parser;
 use ordinal_map::map::OrdinalMap;
-use spec::resolve::{ResolvedSpec, ResolvedTargetSpec};
+use ordinal_map::Ordinal;
+use spec::resolve::{ResolveOptions, ResolvedSpec};
+use std::fs;
+use std::fs::Permissions;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
 
 /// Generate a script which downloads an archive and runs a binary from it.
 #[derive(clap::Parser)]
+#[clap(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
+    #[clap(subcommand)]
+    command: Option<Command>,
+    #[clap(flatten)]
+    gen: GenArgs,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    Fetch(FetchArgs),
+    Gc(GcArgs),
+    Cache(CacheArgs),
+    Update(UpdateArgs),
+}
+
+#[derive(clap::Args)]
+struct GenArgs {
     /// Spec for macos-aarch64.
     #[clap(long, value_name = "spec")]
     macos_aarch64: Option<String>,
     /// Spec for macos-x86_64
     #[clap(long, value_name = "spec")]
     macos_x86_64: Option<String>,
+    /// Spec for both macos-aarch64 and macos-x86_64, e.g. of a universal binary.
+    #[clap(long, value_name = "spec", conflicts_with_all = ["macos_aarch64", "macos_x86_64"])]
+    macos_universal: Option<String>,
     /// Spec for linux-x86_64.
     #[clap(long, value_name = "spec")]
     linux_x86_64: Option<String>,
+    /// Spec for all platforms in `--platforms`, with `{os}` and `{arch}` placeholders
+    /// and optional `os_names=macos:osx,...` and `arch_names=aarch64:arm64,...` items.
+    /// Specs for a platform override it.
+    #[clap(long, value_name = "spec")]
+    spec: Option<String>,
+    /// Platforms for `--spec`, comma-separated; all by default.
+    #[clap(long, value_name = "platforms", value_delimiter = ',', value_parser = TargetPlatform::from_name)]
+    platforms: Vec<TargetPlatform>,
+    /// Shell expression for the cache directory, used instead of
+    /// `$XDG_CACHE_HOME` or `$HOME/Library/Caches`, e.g. `$CI_CACHE`.
+    /// Binaries are cached in `commaslash` subdirectory of it.
+    #[clap(long, value_name = "expr")]
+    cache_dir_expr: Option<String>,
+    /// GitHub API base url, used to list release assets for `github=` specs.
+    #[clap(long, value_name = "url", default_value = DEFAULT_API_URL)]
+    github_api_url: String,
+    /// Version substituted for `{version}` in specs.
+    /// Specs are embedded in the script, so `commaslash update` can change the version.
+    #[clap(long, value_name = "version")]
+    version: Option<String>,
+    /// Before writing scripts, install the binary for the host platform
+    /// and check that it succeeds with these arguments, e.g. `--version`.
+    #[clap(long, value_name = "args", allow_hyphen_values = true)]
+    smoke_test: Option<String>,
+    /// Regex which stdout or stderr of `--smoke-test` must match.
+    #[clap(long, value_name = "regex", requires = "smoke_test")]
+    smoke_test_expect: Option<String>,
     /// Where to write the resulting script; `-` for stdout.
-    #[clap(long, value_name = "output")]
-    output: String,
+    /// When specs have several `path`s, a directory where a script per executable is written.
+    // `Option` because derive fails on missing args even when a subcommand negates them.
+    #[clap(long, value_name = "output", required = true)]
+    output: Option<String>,
 }
 
 pub fn commaslash_main() -> anyhow::Result<()> {
     let args = Args::parse();
+    match args.command {
+        Some(Command::Fetch(args)) => fetch(&args),
+        Some(Command::Gc(args)) => gc(&args),
+        Some(Command::Cache(args)) => cache_main(&args),
+        Some(Command::Update(args)) => update(&args),
+        None => gen_main(args.gen),
+    }
+}
 
-    if args.macos_aarch64.is_none() && args.macos_x86_64.is_none() && args.linux_x86_64.is_none() {
+fn gen_main(args: GenArgs) -> anyhow::Result<()> {
+    if args.macos_aarch64.is_none()
+        && args.macos_x86_64.is_none()
+        && args.macos_universal.is_none()
+        && args.linux_x86_64.is_none()
+        && args.spec.is_none()
+    {
         return Err(anyhow::anyhow!(
             "Must specify at least one spec, e.g. --macos-aarch64=..."
         ));
     }
+    if args.spec.is_none() && !args.platforms.is_empty() {
+        return Err(anyhow::anyhow!("--platforms requires --spec"));
+    }
+    let smoke_test = match &args.smoke_test {
+        Some(smoke_test) => Some(SmokeTest::parse(
+            smoke_test,
+            args.smoke_test_expect.as_deref(),
+        )?),
+        None => None,
+    };
 
-    let specs = OrdinalMap::from_iter([
+    let mut specs: OrdinalMap<TargetPlatform, String> = OrdinalMap::new();
+    if let Some(template) = &args.spec {
+        let template = SpecTemplate::parse(template).context("Failed to parse --spec")?;
+        let platforms = if args.platforms.is_empty() {
+            TargetPlatform::all_values().collect()
+        } else {
+            args.platforms
+        };
+        for target_platform in platforms {
+            specs.insert(target_platform, template.expand(target_platform)?);
+        }
+    }
+    for (target_platform, spec) in [
         (TargetPlatform::LinuxX86_64, args.linux_x86_64),
         (TargetPlatform::MacosAarch64, args.macos_aarch64),
         (TargetPlatform::MacosX86_64, args.macos_x86_64),
-    ]);
+        (TargetPlatform::MacosAarch64, args.macos_universal.clone()),
+        (TargetPlatform::MacosX86_64, args.macos_universal),
+    ] {
+        if let Some(spec) = spec {
+            specs.insert(target_platform, spec);
+        }
+    }
 
-    let mut resolved_spec = ResolvedSpec {
-        specs: OrdinalMap::new(),
+    let resolve_options = ResolveOptions {
+        github_api_url: args.github_api_url,
     };
+    let entrypoints =
+        ResolvedSpec::resolve_sources(&specs, args.version.as_deref(), &resolve_options)?;
 
-    for (target_platform, spec) in specs {
-        let Some(spec) = spec else {
-            continue;
-        };
-        let spec = ResolvedTargetSpec::parse_and_resolve(&spec)
-            .with_context(|| format!("Failed to parse target spec for {target_platform}"))?;
-        resolved_spec.specs.insert(target_platform, spec);
+    let sources = match &args.version {
+        Some(_) => specs
+            .iter()
+            .map(|(target_platform, spec)| (target_platform, ParsedTargetSpec::unpinned(spec)))
+            .collect(),
+        None => OrdinalMap::new(),
+    };
+    let options = GenOptions {
+        cache_dir_expr: args.cache_dir_expr,
+        version: args.version,
+        sources,
+    };
+    if let Some(smoke_test) = &smoke_test {
+        for resolved_spec in &entrypoints {
+            smoke_test.run(resolved_spec, &options)?;
+        }
     }
 
-    let script = gen(&resolved_spec)?;
+    let output = args.output.context("--output is required")?;
+    if let [resolved_spec] = entrypoints.as_slice() {
+        let script = gen(resolved_spec, &options)?;
+        if output == "-" {
+            print!("{}", script);
+        } else {
+            write_script(Path::new(&output), &script)?;
+        }
+        return Ok(());
+    }
 
-    if args.output == "-" {
-        print!("{}", script);
-    } else {
-        fs::write(&args.output, script)?;
-        fs::set_permissions(&args.output, Permissions::from_mode(0o755))?;
+    if output == "-" {
+        return Err(anyhow::anyhow!(
+            "--output must be a directory for specs with several paths"
+        ));
     }
+    let output = Path::new(&output);
+    fs::create_dir_all(output)
+        .with_context(|| format!("Failed to create `{}`", output.display()))?;
+    for resolved_spec in &entrypoints {
+        let script = gen(resolved_spec, &options)?;
+        write_script(&output.join(resolved_spec.exe_name()?), &script)?;
+    }
+
+    Ok(())
+}
 
+fn write_script(path: &Path, script: &str) -> anyhow::Result<()> {
+    fs::write(path, script).with_context(|| format!("Failed to write `{}`", path.display()))?;
+    fs::set_permissions(path, Permissions::from_mode(0o755))?;
     Ok(())
 }
@@ -22,6 +22,7 @@ impl Lockf {
         }
     }
 
+    #[cfg(test)]
     pub(crate) fn test_command(&self) -> Option<&'static str> {
         match self {
             Lockf::Lockf => {
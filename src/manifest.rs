@@ -0,0 +1,172 @@
+//! Manifest of files in an install dir, written to `$install_dir.manifest` at extraction.
+//!
+//! One line per regular file, `<sha256>  ./<path>` as printed by `sha256sum`,
+//! with `\`, newline and carriage return in the path escaped like GNU `sha256sum` does,
+//! and one line per symlink, `./<path> -> <target>`, where path is relative
+//! to the install dir. Lines are sorted by bytes. Symlinks are not followed.
+//! Must match `_commaslash_manifest` in generated scripts byte for byte.
+
+use crate::digest::{Digest, DigestAlgorithm};
+use anyhow::Context;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+/// Compute the manifest of `dir`.
+pub(crate) fn manifest(dir: &Path) -> anyhow::Result<Vec<u8>> {
+    let mut files = Vec::new();
+    let mut symlinks = Vec::new();
+    collect_files(dir, Path::new("."), &mut files, &mut symlinks)
+        .with_context(|| format!("Failed to list `{}`", dir.display()))?;
+    let mut text = Vec::new();
+    for file in files {
+        let sha256 = Digest::of_file(DigestAlgorithm::Sha256, &dir.join(&file))?.hex();
+        let path = file.as_os_str().as_bytes();
+        if path.iter().any(|b| matches!(b, b'\\' | b'\n' | b'\r')) {
+            text.push(b'\\');
+        }
+        text.extend_from_slice(format!("{sha256}  ").as_bytes());
+        for &b in path {
+            match b {
+                b'\\' => text.extend_from_slice(b"\\\\"),
+                b'\n' => text.extend_from_slice(b"\\n"),
+                b'\r' => text.extend_from_slice(b"\\r"),
+                b => text.push(b),
+            }
+        }
+        text.push(b'\n');
+    }
+    for symlink in symlinks {
+        let target = fs::read_link(dir.join(&symlink))?;
+        text.extend_from_slice(symlink.as_os_str().as_bytes());
+        text.extend_from_slice(b" -> ");
+        text.extend_from_slice(target.as_os_str().as_bytes());
+        text.push(b'\n');
+    }
+    // Like `LC_ALL=C sort`, which also splits symlinks with newlines in names.
+    let mut lines: Vec<&[u8]> = text.split_inclusive(|&b| b == b'\n').collect();
+    lines.sort_by_key(|line| &line[..line.len() - 1]);
+    Ok(lines.concat())
+}
+
+fn collect_files(
+    dir: &Path,
+    rel: &Path,
+    files: &mut Vec<PathBuf>,
+    symlinks: &mut Vec<PathBuf>,
+) -> anyhow::Result<()> {
+    for entry in dir.join(rel).read_dir()? {
+        let entry = entry?;
+        let rel = rel.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            collect_files(dir, &rel, files, symlinks)?;
+        } else if file_type.is_file() {
+            files.push(rel);
+        } else if file_type.is_symlink() {
+            symlinks.push(rel);
+        }
+    }
+    Ok(())
+}
+
+/// Write the manifest of `dir` to `manifest_path`.
+pub(crate) fn write_manifest(dir: &Path, manifest_path: &Path) -> anyhow::Result<()> {
+    fs::write(manifest_path, manifest(dir)?)
+        .with_context(|| format!("Failed to write `{}`", manifest_path.display()))
+}
+
+/// `dir` matches the manifest. Missing manifest is a mismatch.
+pub(crate) fn verify(dir: &Path, manifest_path: &Path) -> anyhow::Result<bool> {
+    let expected = match fs::read(manifest_path) {
+        Ok(expected) => expected,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to read `{}`", manifest_path.display()))
+        }
+    };
+    Ok(manifest(dir)? == expected)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gen::verify_functions;
+    use crate::manifest::{manifest, verify, write_manifest};
+    use std::fs;
+    use std::os::unix::fs::symlink;
+    use std::process::Command;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_manifest_matches_script() {
+        let dir = TempDir::new().unwrap();
+        let install = dir.path().join("install");
+        fs::create_dir_all(install.join("bin")).unwrap();
+        fs::create_dir_all(install.join("a b")).unwrap();
+        fs::write(install.join("bin/tool"), "#!/bin/sh\n").unwrap();
+        fs::write(install.join("a b/c d"), "").unwrap();
+        fs::write(install.join("a-c"), "x".repeat(1000)).unwrap();
+        fs::write(install.join("B"), "upper").unwrap();
+        fs::write(install.join("e\\f\ng"), "escaped").unwrap();
+        symlink("bin/tool", install.join("link")).unwrap();
+
+        let expected = String::from_utf8(manifest(&install).unwrap()).unwrap();
+        let mut paths: Vec<&str> = expected
+            .lines()
+            .map(|line| line.split_once("  ").map_or(line, |(_sha256, path)| path))
+            .collect();
+        paths.sort();
+        assert_eq!(
+            vec![
+                "./B",
+                "./a b/c d",
+                "./a-c",
+                "./bin/tool",
+                r"./e\\f\ng",
+                "./link -> bin/tool"
+            ],
+            paths
+        );
+
+        // Generated scripts compute the same manifest.
+        let script = verify_functions();
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(format!("{script}\n_commaslash_manifest \"$1\""))
+            .arg("sh")
+            .arg(&install)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        assert_eq!(expected, String::from_utf8(output.stdout).unwrap());
+    }
+
+    #[test]
+    fn test_manifest_symlink_target() {
+        let dir = TempDir::new().unwrap();
+        let install = dir.path().join("install");
+        fs::create_dir_all(&install).unwrap();
+        fs::write(install.join("a"), "a").unwrap();
+        fs::write(install.join("b"), "b").unwrap();
+        symlink("a", install.join("link")).unwrap();
+        let manifest_path = install.with_extension("manifest");
+        write_manifest(&install, &manifest_path).unwrap();
+        let script_verify = || {
+            Command::new("sh")
+                .arg("-c")
+                .arg(format!("{}\n_commaslash_verify \"$1\"", verify_functions()))
+                .arg("sh")
+                .arg(&install)
+                .status()
+                .unwrap()
+                .success()
+        };
+        assert!(verify(&install, &manifest_path).unwrap());
+        assert!(script_verify());
+
+        fs::remove_file(install.join("link")).unwrap();
+        symlink("b", install.join("link")).unwrap();
+        assert!(!verify(&install, &manifest_path).unwrap());
+        assert!(!script_verify());
+    }
+}
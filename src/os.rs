@@ -1,6 +1,16 @@
+use crate::digest::DigestAlgorithm;
+use crate::digest_command::DigestCommand;
 use crate::lockf::Lockf;
-use crate::sha256_command::Sha256Command;
+use anyhow::Context;
+use std::env;
+use std::path::PathBuf;
 
+/// Like `${name:-}` in shell: unset and empty are the same.
+pub(crate) fn env_var_non_empty(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|v| !v.is_empty())
+}
+
+#[derive(PartialEq, Eq)]
 pub(crate) enum Os {
     Linux,
     Macos,
@@ -15,6 +25,18 @@ impl Os {
         }
     }
 
+    /// Same as `cache_dir_expr`, but evaluated in the current process.
+    pub(crate) fn cache_dir(&self) -> anyhow::Result<PathBuf> {
+        let home = || env_var_non_empty("HOME").context("HOME is not set");
+        match self {
+            Os::Linux => match env_var_non_empty("XDG_CACHE_HOME") {
+                Some(dir) => Ok(PathBuf::from(dir)),
+                None => Ok(PathBuf::from(home()?).join(".cache")),
+            },
+            Os::Macos => Ok(PathBuf::from(home()?).join("Library/Caches")),
+        }
+    }
+
     /// Path to the command to lock the file.
     pub(crate) fn flock(&self) -> Lockf {
         match self {
@@ -23,10 +45,13 @@ impl Os {
         }
     }
 
-    pub(crate) fn sha256_command(&self) -> Sha256Command {
-        match self {
-            Os::Linux => Sha256Command::Sha256sum,
-            Os::Macos => Sha256Command::Shasum,
+    pub(crate) fn digest_command(&self, algorithm: DigestAlgorithm) -> DigestCommand {
+        match (self, algorithm) {
+            (Os::Linux, DigestAlgorithm::Sha256) => DigestCommand::Sha256sum,
+            (Os::Linux, DigestAlgorithm::Sha512) => DigestCommand::Sha512sum,
+            (Os::Macos, DigestAlgorithm::Sha256) => DigestCommand::Shasum256,
+            (Os::Macos, DigestAlgorithm::Sha512) => DigestCommand::Shasum512,
+            (_, DigestAlgorithm::Blake3) => DigestCommand::B3sum,
         }
     }
 }
@@ -5,7 +5,7 @@ use std::ops::Deref;
 #[repr(transparent)]
 pub(crate) struct RelPath(str);
 
-#[derive(derive_more::Display)]
+#[derive(derive_more::Display, Clone, PartialEq, Eq)]
 pub(crate) struct RelPathBuf(String);
 
 impl RelPath {
@@ -24,6 +24,11 @@ impl RelPath {
         if path.ends_with('/') {
             return Err(anyhow::anyhow!("path must not end with /: {path}"));
         }
+        if path.contains(|c: char| c.is_control()) {
+            return Err(anyhow::anyhow!(
+                "path must not contain control characters: {path:?}"
+            ));
+        }
         for component in path.split('/') {
             if component.is_empty() {
                 return Err(anyhow::anyhow!("empty component in path: {path}"));
@@ -38,6 +43,10 @@ impl RelPath {
         Ok(RelPath::unchecked_new(path))
     }
 
+    pub(crate) fn as_str(&self) -> &str {
+        &self.0
+    }
+
     pub(crate) fn components(&self) -> impl DoubleEndedIterator<Item = &str> {
         self.0.split('/')
     }
@@ -45,6 +54,13 @@ impl RelPath {
     pub(crate) fn file_name(&self) -> Option<&str> {
         self.components().last()
     }
+
+    /// Directories containing the path, outermost first, e.g. `a` and `a/b` for `a/b/c`.
+    pub(crate) fn parents(&self) -> impl Iterator<Item = &RelPath> {
+        self.0
+            .match_indices('/')
+            .map(|(i, _)| RelPath::unchecked_new(&self.0[..i]))
+    }
 }
 
 impl RelPathBuf {
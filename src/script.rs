@@ -0,0 +1,163 @@
+//! Metadata embedded in scripts generated by `gen`.
+
+use crate::gen::GenOptions;
+use crate::spec::resolve::{ResolveOptions, ResolvedSpec, ResolvedTargetSpec};
+use crate::target_platform::TargetPlatform;
+use anyhow::Context;
+use ordinal_map::map::OrdinalMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// First lines of every generated script, including scripts generated before specs were embedded.
+pub(crate) const HEADER: &str = concat!("#!/bin/sh\n# @", "generated by commaslash\n");
+/// Prefix of a line which contains the spec for a platform.
+pub(crate) const SPEC_PREFIX: &str = "# commaslash-spec ";
+/// Prefix of a line which contains `GenOptions::cache_dir_expr`.
+pub(crate) const CACHE_DIR_EXPR_PREFIX: &str = "# commaslash-cache-dir-expr ";
+/// Prefix of a line which contains `GenOptions::version`.
+pub(crate) const VERSION_PREFIX: &str = "# commaslash-version ";
+/// Prefix of a line which contains `GenOptions::sources` for a platform.
+pub(crate) const SOURCE_PREFIX: &str = "# commaslash-source ";
+
+pub(crate) struct Script {
+    pub(crate) spec: ResolvedSpec,
+    pub(crate) options: GenOptions,
+}
+
+impl Script {
+    pub(crate) fn parse(script: &str) -> anyhow::Result<Script> {
+        let mut specs = OrdinalMap::new();
+        let mut options = GenOptions::default();
+        for line in script.lines() {
+            if let Some(line) = line.strip_prefix(SPEC_PREFIX) {
+                let (target_platform, spec) = line
+                    .split_once(' ')
+                    .with_context(|| format!("Malformed spec line: {line}"))?;
+                let target_platform = TargetPlatform::from_name(target_platform)?;
+                let spec = ResolvedTargetSpec::parse_and_resolve(
+                    spec,
+                    target_platform,
+                    &ResolveOptions::default(),
+                )
+                .with_context(|| format!("Failed to parse embedded spec for {target_platform}"))?;
+                if specs.insert(target_platform, spec).is_some() {
+                    return Err(anyhow::anyhow!("Duplicate spec for {target_platform}"));
+                }
+            } else if let Some(line) = line.strip_prefix(CACHE_DIR_EXPR_PREFIX) {
+                options.cache_dir_expr = Some(line.to_owned());
+            } else if let Some(line) = line.strip_prefix(VERSION_PREFIX) {
+                options.version = Some(line.to_owned());
+            } else if let Some(line) = line.strip_prefix(SOURCE_PREFIX) {
+                let (target_platform, source) = line
+                    .split_once(' ')
+                    .with_context(|| format!("Malformed source line: {line}"))?;
+                let target_platform = TargetPlatform::from_name(target_platform)?;
+                options.sources.insert(target_platform, source.to_owned());
+            }
+        }
+        if specs.is_empty() {
+            return Err(anyhow::anyhow!(
+                "No embedded spec found; script is not generated by commaslash or is too old"
+            ));
+        }
+        options.validate()?;
+        Ok(Script {
+            spec: ResolvedSpec { specs },
+            options,
+        })
+    }
+
+    pub(crate) fn read(path: &Path) -> anyhow::Result<Script> {
+        let script = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read `{}`", path.display()))?;
+        Script::parse(&script).with_context(|| format!("Failed to parse `{}`", path.display()))
+    }
+}
+
+/// Scripts larger than this are not generated by commaslash.
+const MAX_SCRIPT_SIZE: u64 = 1 << 20;
+
+/// Scripts found under a root.
+#[derive(Default)]
+pub(crate) struct FoundScripts {
+    pub(crate) scripts: Vec<(PathBuf, Script)>,
+    /// Generated scripts which could not be parsed, e.g. those generated
+    /// before specs were embedded. A warning is printed for each.
+    pub(crate) unparsed: Vec<PathBuf>,
+}
+
+/// Recursively find scripts generated by commaslash under `root`.
+///
+/// Symlinks and `.git` directories are not followed.
+pub(crate) fn find_scripts(root: &Path) -> anyhow::Result<FoundScripts> {
+    let mut found = FoundScripts::default();
+    find_scripts_impl(root, &mut found)
+        .with_context(|| format!("Failed to scan `{}`", root.display()))?;
+    Ok(found)
+}
+
+fn find_scripts_impl(path: &Path, found: &mut FoundScripts) -> anyhow::Result<()> {
+    let meta = path.symlink_metadata()?;
+    if meta.is_dir() {
+        if path.file_name() == Some(".git".as_ref()) {
+            return Ok(());
+        }
+        for entry in path.read_dir()? {
+            find_scripts_impl(&entry?.path(), found)?;
+        }
+    } else if meta.is_file() && meta.len() <= MAX_SCRIPT_SIZE {
+        let Ok(content) = fs::read_to_string(path) else {
+            return Ok(());
+        };
+        if !content.starts_with(HEADER) {
+            return Ok(());
+        }
+        match Script::parse(&content) {
+            Ok(script) => found.scripts.push((path.to_owned(), script)),
+            Err(e) => {
+                eprintln!("warning: skipping `{}`: {e:#}", path.display());
+                found.unparsed.push(path.to_owned());
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gen::GenOptions;
+    use crate::script::{find_scripts, HEADER, SPEC_PREFIX};
+    use crate::testutil::write_test_script;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_find_scripts() {
+        let dir = TempDir::new().unwrap();
+        let tools = dir.path().join("tools");
+        let script = write_test_script(
+            &tools,
+            &dir.path().join("archive"),
+            "bin/tool",
+            &GenOptions::default(),
+        );
+        fs::create_dir(dir.path().join(".git")).unwrap();
+        fs::copy(&script, dir.path().join(".git/script")).unwrap();
+        fs::write(dir.path().join("README"), "hello").unwrap();
+        // Mentions the spec prefix, but is not a generated script.
+        fs::write(
+            dir.path().join("notes.md"),
+            format!("Scripts have lines like `{SPEC_PREFIX}linux-x86_64 ...`\n"),
+        )
+        .unwrap();
+        // Generated before specs were embedded.
+        let old = dir.path().join("old");
+        fs::write(&old, format!("{HEADER}exec true\n")).unwrap();
+
+        let found = find_scripts(dir.path()).unwrap();
+        assert_eq!(1, found.scripts.len());
+        assert_eq!(script, found.scripts[0].0);
+        assert_eq!(3, found.scripts[0].1.spec.specs.len());
+        assert_eq!(vec![old], found.unparsed);
+    }
+}
@@ -46,6 +46,22 @@ impl Display for ShArgEscape {
     }
 }
 
+/// Literal string to be placed inside double quotes.
+pub(crate) struct ShDqEscape<'a>(pub(crate) &'a str);
+
+impl Display for ShDqEscape<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for c in self.0.chars() {
+            // These are the only characters which are special inside double quotes.
+            if let '$' | '`' | '"' | '\\' = c {
+                f.write_char('\\')?;
+            }
+            f.write_char(c)?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(derive_more::Display, derive_more::From)]
 pub(crate) enum ShArg {
     Escape(ShArgEscape),
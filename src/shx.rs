@@ -10,6 +10,38 @@ pub(crate) fn file_owner_command(file_expr_raw: &str) -> String {
     format!(r#"stat -f %u {}"#, file_expr_raw)
 }
 
-pub(crate) fn exec_if_exists(exe_path: &str) -> String {
-    format!(r#"test -x "{}" && exec "{}" "$@""#, exe_path, exe_path)
+/// Name of the shell function defined by `Gen::define_exec`.
+pub(crate) const EXEC_FUNCTION: &str = "_commaslash_exec";
+
+/// `install_dir` and `exe_path` are expressions to be placed inside double quotes,
+/// `env` and `command` are quoted words.
+pub(crate) fn exec_command(
+    install_dir: &str,
+    exe_path: &str,
+    env: &[String],
+    command: &[String],
+) -> String {
+    let mut line = format!(r#"{EXEC_FUNCTION} "{install_dir}" "{exe_path}""#);
+    for word in env.iter().chain([&"--".to_owned()]).chain(command) {
+        line.push(' ');
+        line.push_str(word);
+    }
+    line.push_str(r#" "$@""#);
+    line
+}
+
+/// Like `exec_command`, if `test <exe_test> <exe_path>` succeeds.
+pub(crate) fn exec_if_exists(
+    install_dir: &str,
+    exe_path: &str,
+    exe_test: &str,
+    env: &[String],
+    command: &[String],
+) -> String {
+    format!(
+        r#"test {} "{}" && {}"#,
+        exe_test,
+        exe_path,
+        exec_command(install_dir, exe_path, env, command)
+    )
 }
@@ -0,0 +1,149 @@
+//! Publisher signatures of artifacts, checked at generation time.
+//!
+//! Signatures are in minisign format (ed25519), either prehashed or legacy.
+
+use crate::download::download;
+use anyhow::Context;
+use minisign_verify::{PublicKey, Signature};
+
+#[derive(Clone, PartialEq, Eq)]
+pub(crate) struct SignatureSpec {
+    /// Url of the `.minisig` file.
+    pub(crate) sig_url: String,
+    /// Minisign public key in base64, as in the second line of `minisign.pub`.
+    pub(crate) pubkey: String,
+}
+
+impl SignatureSpec {
+    pub(crate) fn new(sig_url: String, pubkey: String) -> anyhow::Result<SignatureSpec> {
+        PublicKey::from_base64(&pubkey).context("Failed to parse minisign pubkey")?;
+        Ok(SignatureSpec { sig_url, pubkey })
+    }
+}
+
+/// Check the minisign signature of the artifact.
+pub(crate) fn verify_signature(artifact: &[u8], signature: &SignatureSpec) -> anyhow::Result<()> {
+    let pubkey = PublicKey::from_base64(&signature.pubkey)?;
+    let sig = download(&signature.sig_url)?;
+    let sig = String::from_utf8(sig).context("Signature is not UTF-8")?;
+    let sig = Signature::decode(&sig).context("Failed to parse minisign signature")?;
+    // Legacy signatures are ed25519 over the whole artifact, which is fine for us.
+    pubkey.verify(artifact, &sig, true)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::digest::{Digest, DigestAlgorithm};
+    use crate::signature::verify_signature;
+    use crate::spec::resolve::{ResolveOptions, ResolvedTargetSpec};
+    use crate::target_platform::TargetPlatform;
+    use base64::prelude::BASE64_STANDARD;
+    use base64::Engine;
+    use ed25519_dalek::{Signer, SigningKey};
+    use std::fs;
+    use std::path::Path;
+    use tempfile::TempDir;
+
+    const KEY_ID: [u8; 8] = [1, 2, 3, 4, 5, 6, 7, 8];
+
+    /// Minisign public key for `key`.
+    fn pubkey(key: &SigningKey) -> String {
+        let mut bytes = b"Ed".to_vec();
+        bytes.extend_from_slice(&KEY_ID);
+        bytes.extend_from_slice(key.verifying_key().as_bytes());
+        BASE64_STANDARD.encode(bytes)
+    }
+
+    /// Legacy minisign signature of `data`.
+    fn sign(key: &SigningKey, data: &[u8]) -> String {
+        let signature = key.sign(data).to_bytes();
+        let mut bytes = b"Ed".to_vec();
+        bytes.extend_from_slice(&KEY_ID);
+        bytes.extend_from_slice(&signature);
+        let trusted_comment = "timestamp:0\tfile:archive.zip";
+        let mut global = signature.to_vec();
+        global.extend_from_slice(trusted_comment.as_bytes());
+        format!(
+            "untrusted comment: test\n{}\ntrusted comment: {trusted_comment}\n{}\n",
+            BASE64_STANDARD.encode(bytes),
+            BASE64_STANDARD.encode(key.sign(&global).to_bytes()),
+        )
+    }
+
+    fn spec(dir: &Path, pubkey: &str) -> ResolvedTargetSpec {
+        let archive = dir.join("archive.zip");
+        let content = fs::read(&archive).unwrap();
+        ResolvedTargetSpec::parse_and_resolve(&format!(
+            "url=file://{} size={} sha256={} path=bin/tool sig_url=file://{}/archive.zip.minisig pubkey={pubkey}",
+            archive.display(),
+            content.len(),
+            Digest::of_bytes(DigestAlgorithm::Sha256, &content).hex(),
+            dir.display(),
+        ),
+            TargetPlatform::LinuxX86_64,
+            &ResolveOptions::default(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_verify_signature() {
+        let dir = TempDir::new().unwrap();
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let other_key = SigningKey::from_bytes(&[8; 32]);
+        let content = b"archive content";
+        fs::write(dir.path().join("archive.zip"), content).unwrap();
+        let sig_path = dir.path().join("archive.zip.minisig");
+
+        let verify = |key| {
+            let spec = spec(dir.path(), &pubkey(key));
+            verify_signature(content, spec.signature.as_ref().unwrap())
+        };
+
+        fs::write(&sig_path, sign(&key, content)).unwrap();
+        verify(&key).unwrap();
+        assert!(verify(&other_key).is_err());
+
+        fs::write(&sig_path, sign(&key, b"other content")).unwrap();
+        assert!(verify(&key).is_err());
+
+        fs::write(&sig_path, sign(&other_key, content)).unwrap();
+        assert!(verify(&key).is_err());
+    }
+
+    #[test]
+    fn test_verify_prehashed_signature() {
+        // Signature of `test` made by `minisign`, from the tests of `minisign-verify`.
+        let pubkey = "RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3";
+        let signature = "untrusted comment: signature from minisign secret key
+RUQf6LRCGA9i559r3g7V1qNyJDApGip8MfqcadIgT9CuhV3EMhHoN1mGTkUidF/z7SrlQgXdy8ofjb7bNJJylDOocrCo8KLzZwo=
+trusted comment: timestamp:1556193335\tfile:test
+y/rUw2y8/hOUYjZU71eHp/Wo1KZ40fGy2VJEDl34XMJM+TX48Ss/17u3IvIfbVR1FkZZSNCisQbuQY+bHwhEBg==
+";
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("archive.zip"), "test").unwrap();
+        fs::write(dir.path().join("archive.zip.minisig"), signature).unwrap();
+        let spec = spec(dir.path(), pubkey);
+        let signature = spec.signature.as_ref().unwrap();
+
+        verify_signature(b"test", signature).unwrap();
+        // Tampered archive.
+        assert!(verify_signature(b"tesT", signature).is_err());
+    }
+
+    #[test]
+    fn test_display_roundtrip() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("archive.zip"), "x").unwrap();
+        let spec = spec(dir.path(), &pubkey(&SigningKey::from_bytes(&[7; 32])));
+        let reparsed = ResolvedTargetSpec::parse_and_resolve(
+            &spec.to_string(),
+            TargetPlatform::LinuxX86_64,
+            &ResolveOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(spec.to_string(), reparsed.to_string());
+        assert!(spec.to_string().contains(" pubkey="));
+    }
+}
@@ -0,0 +1,133 @@
+//! Run the wrapped binary for the host platform after generation.
+
+use crate::cache::CacheDirs;
+use crate::gen::GenOptions;
+use crate::install::{install, InstallOptions};
+use crate::spec::parse::INSTALL_DIR_PLACEHOLDER;
+use crate::spec::resolve::{ResolvedSpec, ResolvedTargetSpec};
+use crate::target_platform::TargetPlatform;
+use anyhow::Context;
+use regex_lite::{Regex, RegexBuilder};
+use std::path::Path;
+use std::process::Command;
+
+pub(crate) struct SmokeTest {
+    /// Arguments of the binary, e.g. `--version`.
+    pub(crate) args: Vec<String>,
+    /// Regex which stdout or stderr of the binary must match; `^` and `$` match at lines.
+    pub(crate) expect: Option<Regex>,
+}
+
+impl SmokeTest {
+    /// Parse `--smoke-test` arguments, split like a shell would.
+    pub(crate) fn parse(args: &str, expect: Option<&str>) -> anyhow::Result<SmokeTest> {
+        let args = shlex::split(args).context("Failed to split --smoke-test arguments")?;
+        let expect = match expect {
+            Some(expect) => Some(
+                RegexBuilder::new(expect)
+                    .multi_line(true)
+                    .build()
+                    .context("Invalid --smoke-test-expect")?,
+            ),
+            None => None,
+        };
+        Ok(SmokeTest { args, expect })
+    }
+
+    /// Install the binary for the host platform into the cache and run it.
+    ///
+    /// Does nothing if there is no spec for the host platform.
+    pub(crate) fn run(&self, spec: &ResolvedSpec, options: &GenOptions) -> anyhow::Result<()> {
+        let target_platform = TargetPlatform::current()?;
+        let Some(target_spec) = spec.specs.get(&target_platform) else {
+            eprintln!("No spec for {target_platform}, skipping smoke test");
+            return Ok(());
+        };
+        let dirs = CacheDirs::from_env(&target_platform.os(), options)?;
+        let exe = install(&dirs, &InstallOptions::from_env(), target_spec)?;
+        self.run_exe(target_spec, &exe)
+            .with_context(|| format!("Smoke test of `{}` failed", exe.display()))
+    }
+
+    fn run_exe(&self, spec: &ResolvedTargetSpec, exe: &Path) -> anyhow::Result<()> {
+        let install_dir = exe
+            .ancestors()
+            .nth(spec.path.components().count())
+            .context("Executable is not in an install dir")?;
+        let install_dir = install_dir.to_str().context("Install dir is not UTF-8")?;
+        let expand = |value: &str| value.replace(INSTALL_DIR_PLACEHOLDER, install_dir);
+
+        let mut command = match &spec.interpreter {
+            Some(interpreter) => {
+                let mut command = Command::new(interpreter);
+                command.args(spec.interpreter_args.iter().map(|arg| expand(arg)));
+                command.arg(exe);
+                command
+            }
+            None => Command::new(exe),
+        };
+        command.args(spec.args.iter().map(|arg| expand(arg)));
+        command.args(&self.args);
+        command.envs(spec.env.iter().map(|(name, value)| (name, expand(value))));
+        let output = command
+            .output()
+            .with_context(|| format!("Failed to run {command:?}"))?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if !output.status.success() {
+            return Err(anyhow::anyhow!(
+                "{command:?} failed: {}\n{stdout}{stderr}",
+                output.status
+            ));
+        }
+        if let Some(expect) = &self.expect {
+            if !expect.is_match(&stdout) && !expect.is_match(&stderr) {
+                return Err(anyhow::anyhow!(
+                    "Output of {command:?} does not match `{expect}`:\n{stdout}{stderr}"
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gen::GenOptions;
+    use crate::smoke_test::SmokeTest;
+    use crate::testutil::{resolve_current, write_zip};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_smoke_test() {
+        let dir = TempDir::new().unwrap();
+        let archive = dir.path().join("tool.zip");
+        write_zip(
+            &archive,
+            &[(
+                "bin/tool",
+                b"#!/bin/sh\ntest \"$1\" = --version && echo \"tool 1.2.3 $TOOL_HOME\"\n",
+                0o755,
+            )],
+        );
+        let spec = resolve_current(
+            &format!(
+                "url=file://{} path=bin/tool env=TOOL_HOME={{install_dir}}",
+                archive.display()
+            ),
+            None,
+        );
+        let options = GenOptions {
+            cache_dir_expr: Some(dir.path().join("cache").to_str().unwrap().to_owned()),
+            ..GenOptions::default()
+        };
+        let smoke_test = |args: &str, expect: Option<&str>| {
+            SmokeTest::parse(args, expect).unwrap().run(&spec, &options)
+        };
+
+        smoke_test("--version", None).unwrap();
+        smoke_test("--version", Some(r"^tool 1\.2\.\d+ /.*$")).unwrap();
+        assert!(smoke_test("--version", Some("^tool 2")).is_err());
+        assert!(smoke_test("--help", None).is_err());
+    }
+}
@@ -1,2 +1,4 @@
 pub(crate) mod parse;
+pub(crate) mod post_extract;
 pub(crate) mod resolve;
+pub(crate) mod template;
@@ -1,15 +1,54 @@
+use crate::digest::{Digest, DigestAlgorithm};
 use crate::rel_path::RelPathBuf;
-use crate::sha256::Sha256Digest;
+use crate::spec::post_extract::PostExtract;
 use anyhow::Context;
 
+/// Replaced with the install directory in `arg` and `env` values at runtime.
+pub(crate) const INSTALL_DIR_PLACEHOLDER: &str = "{install_dir}";
+
+/// `github=<owner>/<repo> tag=<tag> asset=<pattern>`, alternative to `url`.
+pub(crate) struct GithubAsset {
+    pub(crate) repo: String,
+    pub(crate) tag: String,
+    pub(crate) asset: String,
+}
+
 pub(crate) struct ParsedTargetSpec {
-    pub(crate) url: String,
+    pub(crate) url: Option<String>,
+    pub(crate) github: Option<GithubAsset>,
     pub(crate) size: Option<u64>,
-    pub(crate) sha256: Option<Sha256Digest>,
-    pub(crate) path: RelPathBuf,
+    pub(crate) digest: Option<Digest>,
+    /// Executables in the archive; `path` may be repeated to wrap several of them.
+    pub(crate) paths: Vec<RelPathBuf>,
+    /// Leading directories to strip from the archive, each must be the only entry of its level.
+    pub(crate) strip_components: u32,
+    /// Fix-ups of the unpacked archive, in order.
+    pub(crate) post_extract: Vec<PostExtract>,
+    /// Command which runs `path`, which then need not be executable, e.g. `java`.
+    pub(crate) interpreter: Option<String>,
+    /// Arguments of the interpreter before `path`, e.g. `-jar`.
+    pub(crate) interpreter_args: Vec<String>,
+    /// Arguments passed to the executable before the script arguments.
+    pub(crate) args: Vec<String>,
+    /// Environment of the executable, `NAME`, `value` pairs.
+    pub(crate) env: Vec<(String, String)>,
+    pub(crate) sig_url: Option<String>,
+    pub(crate) pubkey: Option<String>,
+    pub(crate) checksums_url: Option<String>,
 }
 
 impl ParsedTargetSpec {
+    /// Spec on one line without size and digest, so it can be resolved for another version.
+    pub(crate) fn unpinned(spec: &str) -> String {
+        spec.split_whitespace()
+            .filter(|part| {
+                let key = part.split_once('=').map_or(*part, |(key, _)| key);
+                !matches!(key, "size" | "sha256" | "sha512" | "blake3")
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
     pub(crate) fn parse(spec: &str) -> anyhow::Result<ParsedTargetSpec> {
         if spec.is_empty() {
             return Err(anyhow::anyhow!("empty spec"));
@@ -17,8 +56,20 @@ impl ParsedTargetSpec {
 
         let mut url = None;
         let mut size = None;
-        let mut sha256 = None;
-        let mut path = None;
+        let mut digest = None;
+        let mut paths: Vec<RelPathBuf> = Vec::new();
+        let mut strip_components = None;
+        let mut post_extract = Vec::new();
+        let mut interpreter = None;
+        let mut interpreter_args = Vec::new();
+        let mut args = Vec::new();
+        let mut env: Vec<(String, String)> = Vec::new();
+        let mut sig_url = None;
+        let mut pubkey = None;
+        let mut checksums_url = None;
+        let mut github = None;
+        let mut tag = None;
+        let mut asset = None;
 
         for part in spec.split_whitespace() {
             if part.is_empty() {
@@ -50,26 +101,136 @@ impl ParsedTargetSpec {
                     let value = value.parse().context("Could not parse size")?;
                     size = Some(value);
                 }
-                "sha256" => {
-                    if sha256.is_some() {
-                        return Err(anyhow::anyhow!("Duplicate sha256"));
+                "sha256" | "sha512" | "blake3" => {
+                    if digest.is_some() {
+                        return Err(anyhow::anyhow!("Duplicate digest: {key}"));
                     }
                     if value.is_empty() {
-                        return Err(anyhow::anyhow!("Empty sha256"));
+                        return Err(anyhow::anyhow!("Empty {key}"));
                     }
-                    let value = Sha256Digest::from_hex(value)?;
-                    sha256 = Some(value);
+                    let algorithm = DigestAlgorithm::from_name(key).unwrap();
+                    let value = Digest::from_hex(algorithm, value)?;
+                    digest = Some(value);
                 }
                 "path" => {
-                    if path.is_some() {
-                        return Err(anyhow::anyhow!("Duplicate path"));
-                    }
                     if value.is_empty() {
                         return Err(anyhow::anyhow!("Empty path"));
                     }
                     let value = RelPathBuf::new(value.to_owned())?;
+                    if paths.iter().any(|path| path.as_str() == value.as_str()) {
+                        return Err(anyhow::anyhow!("Duplicate path: {value}"));
+                    }
 
-                    path = Some(value);
+                    paths.push(value);
+                }
+                "strip_components" => {
+                    if strip_components.is_some() {
+                        return Err(anyhow::anyhow!("Duplicate strip_components"));
+                    }
+                    let value = value.parse().context("Could not parse strip_components")?;
+                    strip_components = Some(value);
+                }
+                "chmod_x" | "symlink" | "remove" => {
+                    post_extract.push(PostExtract::parse(key, value)?);
+                }
+                "interpreter" => {
+                    if interpreter.is_some() {
+                        return Err(anyhow::anyhow!("Duplicate interpreter"));
+                    }
+                    if value.is_empty() {
+                        return Err(anyhow::anyhow!("Empty interpreter"));
+                    }
+                    if value.contains(INSTALL_DIR_PLACEHOLDER) {
+                        return Err(anyhow::anyhow!(
+                            "interpreter must not contain {INSTALL_DIR_PLACEHOLDER}"
+                        ));
+                    }
+                    interpreter = Some(value.to_owned());
+                }
+                "interpreter_arg" => {
+                    if value.is_empty() {
+                        return Err(anyhow::anyhow!("Empty interpreter_arg"));
+                    }
+                    interpreter_args.push(value.to_owned());
+                }
+                "arg" => {
+                    if value.is_empty() {
+                        return Err(anyhow::anyhow!("Empty arg"));
+                    }
+                    args.push(value.to_owned());
+                }
+                "env" => {
+                    let Some((name, value)) = value.split_once('=') else {
+                        return Err(anyhow::anyhow!("env must be <name>=<value>; got: {value}"));
+                    };
+                    if !is_env_name(name) {
+                        return Err(anyhow::anyhow!("Invalid env name: {name:?}"));
+                    }
+                    if env.iter().any(|(n, _)| n == name) {
+                        return Err(anyhow::anyhow!("Duplicate env: {name}"));
+                    }
+                    env.push((name.to_owned(), value.to_owned()));
+                }
+                "sig_url" => {
+                    if sig_url.is_some() {
+                        return Err(anyhow::anyhow!("Duplicate sig_url"));
+                    }
+                    if value.is_empty() {
+                        return Err(anyhow::anyhow!("Empty sig_url"));
+                    }
+                    sig_url = Some(value.to_owned());
+                }
+                "pubkey" => {
+                    if pubkey.is_some() {
+                        return Err(anyhow::anyhow!("Duplicate pubkey"));
+                    }
+                    if value.is_empty() {
+                        return Err(anyhow::anyhow!("Empty pubkey"));
+                    }
+                    pubkey = Some(value.to_owned());
+                }
+                "checksums_url" => {
+                    if checksums_url.is_some() {
+                        return Err(anyhow::anyhow!("Duplicate checksums_url"));
+                    }
+                    if value.is_empty() {
+                        return Err(anyhow::anyhow!("Empty checksums_url"));
+                    }
+                    checksums_url = Some(value.to_owned());
+                }
+                "github" => {
+                    if github.is_some() {
+                        return Err(anyhow::anyhow!("Duplicate github"));
+                    }
+                    let Some((owner, repo)) = value.split_once('/') else {
+                        return Err(anyhow::anyhow!(
+                            "github must be <owner>/<repo>; got: {value}"
+                        ));
+                    };
+                    if owner.is_empty() || repo.is_empty() || repo.contains('/') {
+                        return Err(anyhow::anyhow!(
+                            "github must be <owner>/<repo>; got: {value}"
+                        ));
+                    }
+                    github = Some(value.to_owned());
+                }
+                "tag" => {
+                    if tag.is_some() {
+                        return Err(anyhow::anyhow!("Duplicate tag"));
+                    }
+                    if value.is_empty() {
+                        return Err(anyhow::anyhow!("Empty tag"));
+                    }
+                    tag = Some(value.to_owned());
+                }
+                "asset" => {
+                    if asset.is_some() {
+                        return Err(anyhow::anyhow!("Duplicate asset"));
+                    }
+                    if value.is_empty() {
+                        return Err(anyhow::anyhow!("Empty asset"));
+                    }
+                    asset = Some(value.to_owned());
                 }
                 key => {
                     return Err(anyhow::anyhow!("Unknown key: {key}"));
@@ -77,14 +238,280 @@ impl ParsedTargetSpec {
             }
         }
 
-        let url = url.context("Missing url")?;
-        let path = path.context("Missing path")?;
+        let github = match (github, tag, asset) {
+            (Some(repo), Some(tag), Some(asset)) => Some(GithubAsset { repo, tag, asset }),
+            (None, None, None) => None,
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "github, tag and asset must be specified together"
+                ))
+            }
+        };
+        if url.is_some() == github.is_some() {
+            return Err(anyhow::anyhow!(
+                "Exactly one of url and github must be specified"
+            ));
+        }
+        if paths.is_empty() {
+            return Err(anyhow::anyhow!("Missing path"));
+        }
+        if interpreter.is_none() && !interpreter_args.is_empty() {
+            return Err(anyhow::anyhow!("interpreter_arg requires interpreter"));
+        }
+        if sig_url.is_some() != pubkey.is_some() {
+            return Err(anyhow::anyhow!(
+                "sig_url and pubkey must be specified together"
+            ));
+        }
+        if checksums_url.is_some() && digest.is_some() {
+            return Err(anyhow::anyhow!(
+                "checksums_url and digest must not be specified together"
+            ));
+        }
 
         Ok(ParsedTargetSpec {
             url,
+            github,
             size,
-            sha256,
-            path,
+            digest,
+            paths,
+            strip_components: strip_components.unwrap_or(0),
+            post_extract,
+            interpreter,
+            interpreter_args,
+            args,
+            env,
+            sig_url,
+            pubkey,
+            checksums_url,
         })
     }
 }
+
+fn is_env_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::spec::parse::ParsedTargetSpec;
+
+    const URL: &str = "url=https://example.com/tool.zip path=bin/tool";
+
+    /// Parse each spec and check it fails with the expected error, if any.
+    fn check_parse(cases: &[(String, Option<&str>)]) {
+        for (spec, expected) in cases {
+            match (ParsedTargetSpec::parse(spec), expected) {
+                (Ok(_), None) => {}
+                (Ok(_), Some(expected)) => panic!("{spec}: expected error `{expected}`"),
+                (Err(e), None) => panic!("{spec}: {e:#}"),
+                (Err(e), Some(expected)) => {
+                    assert!(format!("{e:#}").contains(expected), "{spec}: {e:#}")
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_signature() {
+        let sig = "sig_url=https://example.com/tool.zip.minisig pubkey=RWQ";
+        check_parse(&[
+            (format!("{URL} {sig}"), None),
+            (
+                format!("{URL} {sig} sig_url=https://example.com/b"),
+                Some("Duplicate sig_url"),
+            ),
+            (format!("{URL} {sig} pubkey=RWR"), Some("Duplicate pubkey")),
+            (format!("{URL} sig_url= pubkey=RWQ"), Some("Empty sig_url")),
+            (
+                format!("{URL} pubkey=RWQ"),
+                Some("sig_url and pubkey must be specified together"),
+            ),
+        ]);
+        let parsed = ParsedTargetSpec::parse(&format!("{URL} {sig}")).unwrap();
+        assert_eq!(
+            Some("https://example.com/tool.zip.minisig"),
+            parsed.sig_url.as_deref()
+        );
+        assert_eq!(Some("RWQ"), parsed.pubkey.as_deref());
+    }
+
+    #[test]
+    fn test_parse_checksums_url() {
+        check_parse(&[
+            (
+                format!("{URL} checksums_url=https://example.com/SHA256SUMS"),
+                None,
+            ),
+            (
+                format!("{URL} checksums_url=a checksums_url=b"),
+                Some("Duplicate checksums_url"),
+            ),
+            (format!("{URL} checksums_url="), Some("Empty checksums_url")),
+            (
+                format!("{URL} checksums_url=a sha256={}", "0".repeat(64)),
+                Some("checksums_url and digest"),
+            ),
+        ]);
+    }
+
+    #[test]
+    fn test_parse_github() {
+        let github = "github=o/r tag=v1 asset=tool.zip path=bin/tool";
+        check_parse(&[
+            (github.to_owned(), None),
+            (format!("{github} github=o/s"), Some("Duplicate github")),
+            (format!("{github} tag=v2"), Some("Duplicate tag")),
+            (format!("{github} asset=b.zip"), Some("Duplicate asset")),
+            (
+                "github=o tag=v1 asset=a.zip path=t".to_owned(),
+                Some("github must be <owner>/<repo>"),
+            ),
+            (
+                "github=o/r/x tag=v1 asset=a.zip path=t".to_owned(),
+                Some("github must be <owner>/<repo>"),
+            ),
+            (
+                "github=o/r asset=a.zip path=t".to_owned(),
+                Some("must be specified together"),
+            ),
+            (
+                format!("{URL} github=o/r tag=v1 asset=a.zip"),
+                Some("Exactly one of url and github"),
+            ),
+        ]);
+        let github = ParsedTargetSpec::parse(github).unwrap().github.unwrap();
+        assert_eq!(
+            ("o/r", "v1", "tool.zip"),
+            (
+                github.repo.as_str(),
+                github.tag.as_str(),
+                github.asset.as_str()
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse_strip_components() {
+        check_parse(&[
+            (format!("{URL} strip_components=1"), None),
+            (
+                format!("{URL} strip_components=1 strip_components=2"),
+                Some("Duplicate strip_components"),
+            ),
+            (
+                format!("{URL} strip_components=-1"),
+                Some("Could not parse strip_components"),
+            ),
+        ]);
+        let parsed = ParsedTargetSpec::parse(&format!("{URL} strip_components=2")).unwrap();
+        assert_eq!(2, parsed.strip_components);
+        assert_eq!(0, ParsedTargetSpec::parse(URL).unwrap().strip_components);
+    }
+
+    #[test]
+    fn test_parse_paths() {
+        check_parse(&[
+            (format!("{URL} path=bin/tool2"), None),
+            (format!("{URL} path=bin/tool"), Some("Duplicate path")),
+            (format!("{URL} path="), Some("Empty path")),
+            (
+                "url=https://example.com/tool.zip".to_owned(),
+                Some("Missing path"),
+            ),
+        ]);
+        let parsed = ParsedTargetSpec::parse(&format!("{URL} path=bin/tool2")).unwrap();
+        let paths: Vec<&str> = parsed.paths.iter().map(|p| p.as_str()).collect();
+        assert_eq!(vec!["bin/tool", "bin/tool2"], paths);
+    }
+
+    #[test]
+    fn test_parse_args_env() {
+        check_parse(&[
+            (format!("{URL} arg=-v arg={{install_dir}}/lib"), None),
+            (format!("{URL} arg="), Some("Empty arg")),
+            (format!("{URL} env=A=1 env=B={{install_dir}}"), None),
+            (format!("{URL} env=A= env=B=1"), None),
+            (format!("{URL} env=A=1 env=A=2"), Some("Duplicate env: A")),
+            (format!("{URL} env=A"), Some("env must be <name>=<value>")),
+            (format!("{URL} env=1A=1"), Some("Invalid env name")),
+            (format!("{URL} env==1"), Some("Invalid env name")),
+        ]);
+        let parsed =
+            ParsedTargetSpec::parse(&format!("{URL} arg=-v arg=-x env=HOME={{install_dir}}"))
+                .unwrap();
+        assert_eq!(vec!["-v", "-x"], parsed.args);
+        assert_eq!(
+            vec![("HOME".to_owned(), "{install_dir}".to_owned())],
+            parsed.env
+        );
+    }
+
+    #[test]
+    fn test_parse_interpreter() {
+        check_parse(&[
+            (format!("{URL} interpreter=java"), None),
+            (
+                format!("{URL} interpreter=java interpreter=python"),
+                Some("Duplicate interpreter"),
+            ),
+            (format!("{URL} interpreter="), Some("Empty interpreter")),
+            (
+                format!("{URL} interpreter={{install_dir}}/java"),
+                Some("interpreter must not contain {install_dir}"),
+            ),
+            (
+                format!("{URL} interpreter_arg=-jar"),
+                Some("interpreter_arg requires interpreter"),
+            ),
+            (
+                format!("{URL} interpreter=java interpreter_arg="),
+                Some("Empty interpreter_arg"),
+            ),
+        ]);
+        let parsed = ParsedTargetSpec::parse(&format!(
+            "{URL} interpreter=java interpreter_arg=-jar interpreter_arg=-Xmx1g"
+        ))
+        .unwrap();
+        assert_eq!(Some("java"), parsed.interpreter.as_deref());
+        assert_eq!(vec!["-jar", "-Xmx1g"], parsed.interpreter_args);
+    }
+
+    #[test]
+    fn test_parse_post_extract() {
+        check_parse(&[
+            (format!("{URL} chmod_x="), Some("Empty chmod_x")),
+            (
+                format!("{URL} symlink=lib"),
+                Some("symlink must be <link>-><target>"),
+            ),
+            (format!("{URL} symlink=lib->"), Some("Empty symlink target")),
+            (
+                format!("{URL} symlink=lib->../.."),
+                Some("outside of the archive"),
+            ),
+            (
+                format!("{URL} remove=/etc"),
+                Some("path must not start with /"),
+            ),
+        ]);
+        let parsed = ParsedTargetSpec::parse(&format!(
+            "{URL} chmod_x=bin/tool symlink=lib->bin remove=share remove=share"
+        ))
+        .unwrap();
+        let post_extract: Vec<String> = parsed.post_extract.iter().map(|a| a.to_string()).collect();
+        assert_eq!(
+            vec![
+                "chmod_x=bin/tool",
+                "symlink=lib->bin",
+                "remove=share",
+                "remove=share"
+            ],
+            post_extract
+        );
+    }
+}
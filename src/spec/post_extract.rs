@@ -0,0 +1,96 @@
+//! Fix-ups of the unpacked archive, applied before the install becomes visible.
+
+use crate::archive::symlink_target;
+use crate::rel_path::{RelPath, RelPathBuf};
+use std::fmt::{Display, Formatter};
+
+/// Separator of link and target in `symlink=`.
+const SYMLINK_ARROW: &str = "->";
+
+#[derive(Clone, PartialEq, Eq)]
+pub(crate) enum PostExtract {
+    /// `chmod_x=<path>`: make a file executable.
+    ChmodX(RelPathBuf),
+    /// `symlink=<link>-><target>`: create a symlink, `target` is relative to the link.
+    Symlink { link: RelPathBuf, target: String },
+    /// `remove=<path>`: remove a file or a directory if it exists.
+    Remove(RelPathBuf),
+}
+
+impl PostExtract {
+    pub(crate) fn parse(key: &str, value: &str) -> anyhow::Result<PostExtract> {
+        if value.is_empty() {
+            return Err(anyhow::anyhow!("Empty {key}"));
+        }
+        match key {
+            "chmod_x" => Ok(PostExtract::ChmodX(RelPathBuf::new(value.to_owned())?)),
+            "symlink" => {
+                let Some((link, target)) = value.split_once(SYMLINK_ARROW) else {
+                    return Err(anyhow::anyhow!(
+                        "symlink must be <link>{SYMLINK_ARROW}<target>; got: {value}"
+                    ));
+                };
+                let link = RelPathBuf::new(link.to_owned())?;
+                if target.is_empty() {
+                    return Err(anyhow::anyhow!("Empty symlink target"));
+                }
+                // Fails if the target is outside of the install.
+                symlink_target(link.as_str(), target)?;
+                Ok(PostExtract::Symlink {
+                    link,
+                    target: target.to_owned(),
+                })
+            }
+            "remove" => Ok(PostExtract::Remove(RelPathBuf::new(value.to_owned())?)),
+            key => Err(anyhow::anyhow!("Unknown post-extract action: {key}")),
+        }
+    }
+
+    /// Whether the action makes `path` executable or creates it or its parent.
+    pub(crate) fn prepares(&self, path: &RelPath) -> bool {
+        match self {
+            PostExtract::ChmodX(p) => p.as_str() == path.as_str(),
+            PostExtract::Symlink { link, .. } => path
+                .as_str()
+                .strip_prefix(link.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/')),
+            PostExtract::Remove(_) => false,
+        }
+    }
+}
+
+/// Formatted as a spec item.
+impl Display for PostExtract {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PostExtract::ChmodX(path) => write!(f, "chmod_x={path}"),
+            PostExtract::Symlink { link, target } => {
+                write!(f, "symlink={link}{SYMLINK_ARROW}{target}")
+            }
+            PostExtract::Remove(path) => write!(f, "remove={path}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::spec::post_extract::PostExtract;
+
+    #[test]
+    fn test_parse() {
+        for item in [
+            "chmod_x=bin/tool",
+            "symlink=bin/tool->../libexec/tool",
+            "remove=share/doc",
+        ] {
+            let (key, value) = item.split_once('=').unwrap();
+            assert_eq!(item, PostExtract::parse(key, value).unwrap().to_string());
+        }
+        assert!(PostExtract::parse("chmod_x", "../tool").is_err());
+        assert!(PostExtract::parse("symlink", "bin/tool").is_err());
+        assert!(PostExtract::parse("symlink", "bin/tool->").is_err());
+        assert!(PostExtract::parse("symlink", "bin/tool->../../etc/passwd").is_err());
+        assert!(PostExtract::parse("symlink", "bin/tool->/usr/bin/tool").is_err());
+        assert!(PostExtract::parse("remove", "").is_err());
+    }
+}
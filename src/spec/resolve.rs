@@ -1,43 +1,369 @@
+use crate::archive::ArchiveFormat;
+use crate::checksums::resolve_checksum;
+use crate::digest::{Digest, DigestAlgorithm};
+use crate::download::download;
+use crate::exe_header::check_exe_header;
+use crate::github::{
+    has_placeholders, pick_asset, release_assets, release_download_url, DEFAULT_API_URL,
+};
+use crate::os::Os;
 use crate::rel_path::RelPathBuf;
-use crate::sha256::Sha256Digest;
-use crate::spec::parse::ParsedTargetSpec;
+use crate::signature::{verify_signature, SignatureSpec};
+use crate::spec::parse::{GithubAsset, ParsedTargetSpec};
+use crate::spec::post_extract::PostExtract;
 use crate::target_platform::TargetPlatform;
 use anyhow::Context;
 use ordinal_map::map::OrdinalMap;
-use crate::archive::ArchiveFormat;
+use std::fmt::{Display, Formatter};
 
+#[derive(Clone, PartialEq, Eq)]
 pub(crate) struct ResolvedTargetSpec {
     pub(crate) url: String,
     pub(crate) size: u64,
-    pub(crate) sha256: Sha256Digest,
+    pub(crate) digest: Digest,
     pub(crate) path: RelPathBuf,
+    pub(crate) strip_components: u32,
+    pub(crate) post_extract: Vec<PostExtract>,
+    pub(crate) interpreter: Option<String>,
+    pub(crate) interpreter_args: Vec<String>,
+    pub(crate) args: Vec<String>,
+    pub(crate) env: Vec<(String, String)>,
     pub(crate) archive_format: ArchiveFormat,
+    pub(crate) signature: Option<SignatureSpec>,
+}
+
+/// How to resolve parts of specs not given explicitly.
+pub(crate) struct ResolveOptions {
+    /// Base url of GitHub API, for `github=` specs.
+    pub(crate) github_api_url: String,
+}
+
+impl Default for ResolveOptions {
+    fn default() -> Self {
+        ResolveOptions {
+            github_api_url: DEFAULT_API_URL.to_owned(),
+        }
+    }
 }
 
 impl ResolvedTargetSpec {
-    pub(crate) fn parse_and_resolve(spec: &str) -> anyhow::Result<ResolvedTargetSpec> {
+    pub(crate) fn parse_and_resolve(
+        spec: &str,
+        target_platform: TargetPlatform,
+        options: &ResolveOptions,
+    ) -> anyhow::Result<ResolvedTargetSpec> {
+        let (mut specs, _) = Self::parse_and_resolve_impl(spec, target_platform, options)?;
+        if specs.len() != 1 {
+            return Err(anyhow::anyhow!("Expecting a single path"));
+        }
+        Ok(specs.remove(0))
+    }
+
+    /// Resolve a spec for each `path`.
+    /// Also return the artifact if it was downloaded to compute size or digest.
+    fn parse_and_resolve_impl(
+        spec: &str,
+        target_platform: TargetPlatform,
+        options: &ResolveOptions,
+    ) -> anyhow::Result<(Vec<ResolvedTargetSpec>, Option<Vec<u8>>)> {
         let ParsedTargetSpec {
             url,
+            github,
             size,
-            sha256,
-            path,
+            digest,
+            paths,
+            strip_components,
+            post_extract,
+            interpreter,
+            interpreter_args,
+            args,
+            env,
+            sig_url,
+            pubkey,
+            checksums_url,
         } = ParsedTargetSpec::parse(spec)?;
+        let mut listed = None;
+        let url = match (url, github) {
+            (Some(url), _) => url,
+            (None, Some(GithubAsset { repo, tag, asset })) => {
+                let known = size.is_some() && (digest.is_some() || checksums_url.is_some());
+                if known && !has_placeholders(&asset) {
+                    release_download_url(&repo, &tag, &asset)
+                } else {
+                    let assets = release_assets(&options.github_api_url, &repo, &tag)?;
+                    let asset = pick_asset(assets, &asset, target_platform, &tag)?;
+                    let url = asset.browser_download_url.clone();
+                    listed = Some(asset);
+                    url
+                }
+            }
+            (None, None) => unreachable!("checked by parse"),
+        };
         let archive_format = ArchiveFormat::from_file_path(&url)?;
-        Ok(ResolvedTargetSpec {
+        let signature = match (sig_url, pubkey) {
+            (Some(sig_url), Some(pubkey)) => Some(SignatureSpec::new(sig_url, pubkey)?),
+            _ => None,
+        };
+        let listed_digest = match listed.as_ref().and_then(|a| a.digest.as_deref()) {
+            Some(digest) => Some(Digest::parse(digest)?),
+            None => None,
+        };
+        let digest = match (digest, checksums_url, listed_digest) {
+            (Some(digest), _, _) => Some(digest),
+            (None, Some(checksums_url), _) => Some(resolve_checksum(&checksums_url, &url)?),
+            (None, None, digest) => digest,
+        };
+        let size = size.or(listed.map(|a| a.size));
+        let (size, digest, artifact) = match (size, digest) {
+            (Some(size), Some(digest)) => (size, digest, None),
+            (size, digest) => {
+                // Pin whatever the url serves now.
+                let artifact = download(&url)?;
+                let algorithm = digest.map_or(DigestAlgorithm::Sha256, |d| d.algorithm());
+                let actual_digest = Digest::of_bytes(algorithm, &artifact);
+                let actual_size = artifact.len() as u64;
+                if let Some(size) = size.filter(|&size| size != actual_size) {
+                    return Err(anyhow::anyhow!(
+                        "Artifact `{url}` size mismatch: expected {size}, got {actual_size}"
+                    ));
+                }
+                if let Some(digest) = digest.filter(|&digest| digest != actual_digest) {
+                    return Err(anyhow::anyhow!(
+                        "Artifact `{url}` digest mismatch: expected {digest}, got {actual_digest}"
+                    ));
+                }
+                (actual_size, actual_digest, Some(artifact))
+            }
+        };
+        let specs = paths
+            .into_iter()
+            .map(|path| ResolvedTargetSpec {
+                url: url.clone(),
+                size,
+                digest,
+                path,
+                strip_components,
+                post_extract: post_extract.clone(),
+                interpreter: interpreter.clone(),
+                interpreter_args: interpreter_args.clone(),
+                args: args.clone(),
+                env: env.clone(),
+                archive_format,
+                signature: signature.clone(),
+            })
+            .collect();
+        Ok((specs, artifact))
+    }
+
+    /// Download the artifact and check it against the pinned size and digest.
+    fn download_artifact(&self) -> anyhow::Result<Vec<u8>> {
+        let artifact = download(&self.url)?;
+        if artifact.len() as u64 != self.size {
+            return Err(anyhow::anyhow!(
+                "Artifact `{}` size mismatch: expected {}, got {}",
+                self.url,
+                self.size,
+                artifact.len()
+            ));
+        }
+        let digest = Digest::of_bytes(self.digest.algorithm(), &artifact);
+        if digest != self.digest {
+            return Err(anyhow::anyhow!(
+                "Artifact `{}` digest mismatch: expected {}, got {}",
+                self.url,
+                self.digest,
+                digest
+            ));
+        }
+        Ok(artifact)
+    }
+}
+
+/// Formatted as a spec, parseable by `parse_and_resolve`.
+impl Display for ResolvedTargetSpec {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let ResolvedTargetSpec {
             url,
-            size: size.context("inferring size is not implemented")?,
-            sha256: sha256.context("inferring sha256 is not implemented")?,
+            size,
+            digest,
             path,
-            archive_format,
-        })
+            strip_components,
+            post_extract,
+            interpreter,
+            interpreter_args,
+            args,
+            env,
+            archive_format: _,
+            signature,
+        } = self;
+        write!(
+            f,
+            "url={url} size={size} {}={} path={path}",
+            digest.algorithm(),
+            digest.hex()
+        )?;
+        if *strip_components != 0 {
+            write!(f, " strip_components={strip_components}")?;
+        }
+        for action in post_extract {
+            write!(f, " {action}")?;
+        }
+        if let Some(interpreter) = interpreter {
+            write!(f, " interpreter={interpreter}")?;
+        }
+        for arg in interpreter_args {
+            write!(f, " interpreter_arg={arg}")?;
+        }
+        for arg in args {
+            write!(f, " arg={arg}")?;
+        }
+        for (name, value) in env {
+            write!(f, " env={name}={value}")?;
+        }
+        if let Some(SignatureSpec { sig_url, pubkey }) = signature {
+            write!(f, " sig_url={sig_url} pubkey={pubkey}")?;
+        }
+        Ok(())
     }
 }
 
+/// Specs for the paths of a source, and the artifact if it was downloaded.
+type SourceResolution = (Vec<ResolvedTargetSpec>, Option<Vec<u8>>);
+
 pub(crate) struct ResolvedSpec {
     pub(crate) specs: OrdinalMap<TargetPlatform, ResolvedTargetSpec>,
 }
 
 impl ResolvedSpec {
+    /// Resolve specs as given to the generator, substituting `version` for `{version}`.
+    ///
+    /// Return a spec per executable, in the order of `path`s of the first platform;
+    /// executables are matched across platforms by file name.
+    ///
+    /// When the artifact is downloaded anyway, is local or has a signature to check,
+    /// also check that each `path` in it is an executable file.
+    pub(crate) fn resolve_sources(
+        sources: &OrdinalMap<TargetPlatform, String>,
+        version: Option<&str>,
+        options: &ResolveOptions,
+    ) -> anyhow::Result<Vec<ResolvedSpec>> {
+        let mut entrypoints: Vec<ResolvedSpec> = Vec::new();
+        // Specs resolved so far with the source and the OS they were resolved for.
+        let mut resolved: Vec<(String, Os, SourceResolution)> = Vec::new();
+        for (target_platform, source) in sources {
+            let spec = match version {
+                Some(version) => source.replace("{version}", version),
+                None if source.contains("{version}") => {
+                    return Err(anyhow::anyhow!(
+                        "Spec for {target_platform} contains `{{version}}`, but no version is given; pass `--version`"
+                    ));
+                }
+                None => source.clone(),
+            };
+            // A source shared by platforms of one OS, like `--macos-universal`,
+            // is resolved and downloaded once.
+            let reused = resolved
+                .iter()
+                .find(|(s, os, _)| *s == spec && *os == target_platform.os());
+            let (specs, artifact) = match reused {
+                Some((_, _, resolution)) => resolution.clone(),
+                None => {
+                    let resolution = Self::resolve_source(&spec, target_platform, options)?;
+                    resolved.push((spec, target_platform.os(), resolution.clone()));
+                    resolution
+                }
+            };
+            if let Some(artifact) = &artifact {
+                // Paths prepared by post-extract actions are checked by the script.
+                for spec in specs.iter().filter(|spec| {
+                    !spec
+                        .post_extract
+                        .iter()
+                        .any(|action| action.prepares(&spec.path))
+                }) {
+                    let header = spec
+                        .archive_format
+                        .check_path(
+                            artifact,
+                            spec.strip_components,
+                            &spec.path,
+                            spec.interpreter.is_none(),
+                        )
+                        .with_context(|| {
+                            format!("Invalid archive `{}` for {target_platform}", spec.url)
+                        })?;
+                    // Scripts for an interpreter are not checked.
+                    if spec.interpreter.is_none() {
+                        check_exe_header(&header, target_platform)
+                            .with_context(|| format!("Invalid executable `{}`", spec.path))?;
+                    }
+                }
+            }
+            for spec in specs {
+                let name = spec
+                    .path
+                    .file_name()
+                    .context("path must have a file name")?
+                    .to_owned();
+                let existing = entrypoints
+                    .iter()
+                    .position(|entrypoint| entrypoint.exe_name().ok() == Some(name.as_str()));
+                let index = match existing {
+                    Some(index) => index,
+                    None => {
+                        entrypoints.push(ResolvedSpec {
+                            specs: OrdinalMap::new(),
+                        });
+                        entrypoints.len() - 1
+                    }
+                };
+                if entrypoints[index]
+                    .specs
+                    .insert(target_platform, spec)
+                    .is_some()
+                {
+                    return Err(anyhow::anyhow!(
+                        "Several paths named `{name}` for {target_platform}"
+                    ));
+                }
+            }
+        }
+        for entrypoint in &entrypoints {
+            if entrypoint.specs.len() != sources.len() {
+                return Err(anyhow::anyhow!(
+                    "Executable `{}` is not in specs of all platforms",
+                    entrypoint.exe_name()?
+                ));
+            }
+        }
+        Ok(entrypoints)
+    }
+
+    /// Resolve the spec for a platform, downloading the artifact if needed
+    /// and verifying its signature.
+    fn resolve_source(
+        spec: &str,
+        target_platform: TargetPlatform,
+        options: &ResolveOptions,
+    ) -> anyhow::Result<SourceResolution> {
+        let (specs, artifact) =
+            ResolvedTargetSpec::parse_and_resolve_impl(spec, target_platform, options)
+                .with_context(|| format!("Failed to parse target spec for {target_platform}"))?;
+        let first = &specs[0];
+        let artifact = match artifact {
+            Some(artifact) => Some(artifact),
+            None if first.signature.is_some() || first.url.starts_with("file://") => {
+                Some(first.download_artifact()?)
+            }
+            None => None,
+        };
+        if let (Some(artifact), Some(signature)) = (&artifact, &first.signature) {
+            verify_signature(artifact, signature)
+                .with_context(|| format!("Failed to verify signature for {target_platform}"))?;
+        }
+        Ok((specs, artifact))
+    }
+
     pub(crate) fn exe_name(&self) -> anyhow::Result<&str> {
         let target_spec = self
             .specs
@@ -50,3 +376,121 @@ impl ResolvedSpec {
             .context("path must have a file name")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::spec::resolve::{ResolveOptions, ResolvedSpec};
+    use crate::target_platform::TargetPlatform;
+    use crate::testutil::write_zip;
+    use ordinal_map::map::OrdinalMap;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_resolve_sources_entrypoints() {
+        let dir = TempDir::new().unwrap();
+        let linux = dir.path().join("linux.zip");
+        let macos = dir.path().join("macos.zip");
+        for archive in [&linux, &macos] {
+            write_zip(
+                archive,
+                &[("bin/clang", b"", 0o755), ("bin/clang-format", b"", 0o755)],
+            );
+        }
+        let resolve = |linux_paths: &str, macos_paths: &str| {
+            let sources = OrdinalMap::from_iter([
+                (
+                    TargetPlatform::LinuxX86_64,
+                    format!("url=file://{} {linux_paths}", linux.display()),
+                ),
+                (
+                    TargetPlatform::MacosAarch64,
+                    format!("url=file://{} {macos_paths}", macos.display()),
+                ),
+            ]);
+            ResolvedSpec::resolve_sources(&sources, None, &ResolveOptions::default())
+        };
+
+        let entrypoints = resolve(
+            "path=bin/clang path=bin/clang-format",
+            "path=bin/clang-format path=bin/clang",
+        )
+        .unwrap();
+        let names: Vec<&str> = entrypoints.iter().map(|e| e.exe_name().unwrap()).collect();
+        assert_eq!(vec!["clang", "clang-format"], names);
+        for entrypoint in &entrypoints {
+            assert_eq!(2, entrypoint.specs.len());
+            for spec in entrypoint.specs.values() {
+                assert!(spec.path.as_str().ends_with(entrypoint.exe_name().unwrap()));
+            }
+        }
+
+        assert!(resolve("path=bin/clang path=bin/clang-format", "path=bin/clang").is_err());
+        assert!(resolve("path=bin/clang path=bin/clang", "path=bin/clang").is_err());
+        assert!(resolve("path=bin/clang path=bin/missing", "path=bin/clang").is_err());
+    }
+
+    #[test]
+    fn test_resolve_sources_version() {
+        let dir = TempDir::new().unwrap();
+        write_zip(
+            &dir.path().join("tool-1.0.zip"),
+            &[("bin/tool", b"", 0o755)],
+        );
+        let sources = OrdinalMap::from_iter([(
+            TargetPlatform::LinuxX86_64,
+            format!(
+                "url=file://{}/tool-{{version}}.zip path=bin/tool",
+                dir.path().display()
+            ),
+        )]);
+        let resolve =
+            |version| ResolvedSpec::resolve_sources(&sources, version, &ResolveOptions::default());
+
+        resolve(Some("1.0")).unwrap();
+        let error = format!("{:#}", resolve(None).err().unwrap());
+        assert!(error.contains("pass `--version`"), "{error}");
+    }
+
+    #[test]
+    fn test_resolve_sources_exe_header() {
+        let dir = TempDir::new().unwrap();
+        let archive = dir.path().join("tool.zip");
+        let mut macho = 0xfeedfacf_u32.to_le_bytes().to_vec();
+        macho.extend(0x0100_000c_u32.to_le_bytes());
+        macho.resize(32, 0);
+        write_zip(&archive, &[("bin/tool", &macho, 0o755)]);
+        let resolve = |target_platform, extra: &str| {
+            let sources = OrdinalMap::from_iter([(
+                target_platform,
+                format!("url=file://{} path=bin/tool {extra}", archive.display()),
+            )]);
+            ResolvedSpec::resolve_sources(&sources, None, &ResolveOptions::default()).map(|_| ())
+        };
+
+        resolve(TargetPlatform::MacosAarch64, "").unwrap();
+        let error = format!(
+            "{:#}",
+            resolve(TargetPlatform::LinuxX86_64, "").unwrap_err()
+        );
+        assert!(
+            error.contains("is a Mach-O binary for arm64, which does not run on linux-x86_64"),
+            "{error}"
+        );
+        resolve(TargetPlatform::LinuxX86_64, "interpreter=sh").unwrap();
+
+        // A source shared by both macOS platforms is resolved once,
+        // but the binary is still checked for each of them.
+        let source = format!("url=file://{} path=bin/tool", archive.display());
+        let sources = OrdinalMap::from_iter([
+            (TargetPlatform::MacosAarch64, source.clone()),
+            (TargetPlatform::MacosX86_64, source),
+        ]);
+        let error = format!(
+            "{:#}",
+            ResolvedSpec::resolve_sources(&sources, None, &ResolveOptions::default())
+                .map(|_| ())
+                .unwrap_err()
+        );
+        assert!(error.contains("does not run on macos-x86_64"), "{error}");
+    }
+}
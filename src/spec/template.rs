@@ -0,0 +1,172 @@
+//! Spec for all platforms with `{os}` and `{arch}` placeholders, passed as `--spec`.
+
+use crate::target_platform::TargetPlatform;
+use anyhow::Context;
+use ordinal_map::Ordinal;
+use std::collections::BTreeMap;
+
+/// Placeholders substituted after expansion: `{version}` when specs are resolved,
+/// `{install_dir}` at runtime.
+const LATER_PLACEHOLDERS: &[&str] = &["{version}", "{install_dir}"];
+
+pub(crate) struct SpecTemplate {
+    /// Spec items other than `os_names` and `arch_names`.
+    parts: Vec<String>,
+    /// Upstream spelling of `{os}` by `TargetPlatform::os_name`, e.g. `macos` to `osx`.
+    os_names: BTreeMap<String, String>,
+    /// Upstream spelling of `{arch}` by `TargetPlatform::arch_name`, e.g. `aarch64` to `arm64`.
+    arch_names: BTreeMap<String, String>,
+}
+
+impl SpecTemplate {
+    pub(crate) fn parse(template: &str) -> anyhow::Result<SpecTemplate> {
+        let mut parts = Vec::new();
+        let mut os_names = None;
+        let mut arch_names = None;
+        for part in template.split_whitespace() {
+            match part.split_once('=') {
+                Some(("os_names", value)) => {
+                    if os_names.is_some() {
+                        return Err(anyhow::anyhow!("Duplicate os_names"));
+                    }
+                    let known = TargetPlatform::all_values().map(|p| p.os_name());
+                    os_names = Some(parse_names(value, known).context("Invalid os_names")?);
+                }
+                Some(("arch_names", value)) => {
+                    if arch_names.is_some() {
+                        return Err(anyhow::anyhow!("Duplicate arch_names"));
+                    }
+                    let known = TargetPlatform::all_values().map(|p| p.arch_name());
+                    arch_names = Some(parse_names(value, known).context("Invalid arch_names")?);
+                }
+                _ => parts.push(part.to_owned()),
+            }
+        }
+        Ok(SpecTemplate {
+            parts,
+            os_names: os_names.unwrap_or_default(),
+            arch_names: arch_names.unwrap_or_default(),
+        })
+    }
+
+    /// Spec for the platform.
+    ///
+    /// In `asset=`, placeholders without a map are left to match common spellings.
+    /// Fails on unknown placeholders, e.g. misspelled ones.
+    pub(crate) fn expand(&self, target_platform: TargetPlatform) -> anyhow::Result<String> {
+        let os = target_platform.os_name();
+        let os = self.os_names.get(os).map_or(os, String::as_str);
+        let arch = target_platform.arch_name();
+        let arch = self.arch_names.get(arch).map_or(arch, String::as_str);
+        let mut spec = Vec::new();
+        for part in &self.parts {
+            let is_asset = part.starts_with("asset=");
+            let mut part = part.clone();
+            if !is_asset || !self.os_names.is_empty() {
+                part = part.replace("{os}", os);
+            }
+            if !is_asset || !self.arch_names.is_empty() {
+                part = part.replace("{arch}", arch);
+            }
+            let allowed: &[&str] = if is_asset { &["{os}", "{arch}"] } else { &[] };
+            if let Some(placeholder) = unknown_placeholder(&part, allowed) {
+                return Err(anyhow::anyhow!(
+                    "Unknown placeholder `{placeholder}` in `{part}`"
+                ));
+            }
+            spec.push(part);
+        }
+        Ok(spec.join(" "))
+    }
+}
+
+/// First `{...}` in `part` which is neither in `LATER_PLACEHOLDERS` nor in `allowed`.
+fn unknown_placeholder<'a>(part: &'a str, allowed: &[&str]) -> Option<&'a str> {
+    part.match_indices('{')
+        .map(|(start, _)| match part[start..].find('}') {
+            Some(len) => &part[start..=start + len],
+            None => &part[start..],
+        })
+        .find(|placeholder| {
+            !LATER_PLACEHOLDERS.contains(placeholder) && !allowed.contains(placeholder)
+        })
+}
+
+/// Parse `<name>:<upstream>,...`.
+fn parse_names<'a>(
+    value: &str,
+    known: impl Iterator<Item = &'a str>,
+) -> anyhow::Result<BTreeMap<String, String>> {
+    let known: Vec<&str> = known.collect();
+    let mut names = BTreeMap::new();
+    for item in value.split(',') {
+        let (name, upstream) = item
+            .split_once(':')
+            .with_context(|| format!("Expecting `<name>:<upstream name>`, got `{item}`"))?;
+        if !known.contains(&name) {
+            return Err(anyhow::anyhow!(
+                "Unknown name `{name}`, expecting one of: {}",
+                known.join(", ")
+            ));
+        }
+        if upstream.is_empty() {
+            return Err(anyhow::anyhow!("Empty upstream name for `{name}`"));
+        }
+        if names.insert(name.to_owned(), upstream.to_owned()).is_some() {
+            return Err(anyhow::anyhow!("Duplicate name `{name}`"));
+        }
+    }
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::spec::template::SpecTemplate;
+    use crate::target_platform::TargetPlatform;
+
+    #[test]
+    fn test_expand() {
+        let template = SpecTemplate::parse(
+            "url=https://example.com/v{version}/protoc-{version}-{os}-{arch}.zip
+             os_names=macos:osx arch_names=aarch64:aarch_64 path=bin/protoc",
+        )
+        .unwrap();
+        assert_eq!(
+            "url=https://example.com/v{version}/protoc-{version}-osx-aarch_64.zip path=bin/protoc",
+            template.expand(TargetPlatform::MacosAarch64).unwrap()
+        );
+        assert_eq!(
+            "url=https://example.com/v{version}/protoc-{version}-linux-x86_64.zip path=bin/protoc",
+            template.expand(TargetPlatform::LinuxX86_64).unwrap()
+        );
+
+        let template =
+            SpecTemplate::parse("github=o/r tag=v1 asset=tool-{os}-{arch}.zip path=tool").unwrap();
+        assert_eq!(
+            "github=o/r tag=v1 asset=tool-{os}-{arch}.zip path=tool",
+            template.expand(TargetPlatform::MacosX86_64).unwrap()
+        );
+
+        let template = SpecTemplate::parse(
+            "url=https://example.com/{os}-{arch}.zip path=tool arg=--home={install_dir}",
+        )
+        .unwrap();
+        assert!(template.expand(TargetPlatform::LinuxX86_64).is_ok());
+        for spec in [
+            "url=https://example.com/{OS}-{arch}.zip path=tool",
+            "url=https://example.com/{os}-{arch.zip path=tool",
+            "url=https://example.com/v{versoin}/tool.zip path=tool",
+            "github=o/r tag=v1 asset=tool-{platform}.zip path=tool",
+        ] {
+            let template = SpecTemplate::parse(spec).unwrap();
+            assert!(
+                template.expand(TargetPlatform::LinuxX86_64).is_err(),
+                "{spec}"
+            );
+        }
+
+        assert!(SpecTemplate::parse("os_names=windows:win").is_err());
+        assert!(SpecTemplate::parse("arch_names=aarch64").is_err());
+        assert!(SpecTemplate::parse("arch_names=aarch64:arm64,aarch64:aarch64").is_err());
+    }
+}
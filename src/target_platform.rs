@@ -1,6 +1,9 @@
 use crate::os::Os;
+use anyhow::Context;
+use ordinal_map::Ordinal;
+use std::process::Command;
 
-#[derive(ordinal_map::Ordinal, derive_more::Display)]
+#[derive(ordinal_map::Ordinal, derive_more::Display, Copy, Clone, PartialEq, Eq)]
 pub(crate) enum TargetPlatform {
     #[display("linux-x86_64")]
     LinuxX86_64,
@@ -19,6 +22,45 @@ impl TargetPlatform {
         }
     }
 
+    /// Inverse of `Display`.
+    pub(crate) fn from_name(name: &str) -> anyhow::Result<TargetPlatform> {
+        TargetPlatform::all_values()
+            .find(|p| p.to_string() == name)
+            .with_context(|| format!("Unknown target platform: `{name}`"))
+    }
+
+    /// Platform of the current host, determined the same way as generated scripts do.
+    pub(crate) fn current() -> anyhow::Result<TargetPlatform> {
+        let output = Command::new("uname")
+            .arg("-sm")
+            .output()
+            .context("Failed to run `uname -sm`")?;
+        if !output.status.success() {
+            return Err(anyhow::anyhow!("`uname -sm` failed"));
+        }
+        let uname_sm = String::from_utf8(output.stdout)?;
+        let uname_sm = uname_sm.trim();
+        TargetPlatform::all_values()
+            .find(|p| p.uname_sm() == uname_sm)
+            .with_context(|| format!("Unsupported platform: `{uname_sm}`"))
+    }
+
+    /// `{os}` in spec templates.
+    pub(crate) fn os_name(&self) -> &'static str {
+        match self.os() {
+            Os::Linux => "linux",
+            Os::Macos => "macos",
+        }
+    }
+
+    /// `{arch}` in spec templates.
+    pub(crate) fn arch_name(&self) -> &'static str {
+        match self {
+            TargetPlatform::LinuxX86_64 | TargetPlatform::MacosX86_64 => "x86_64",
+            TargetPlatform::MacosAarch64 => "aarch64",
+        }
+    }
+
     pub(crate) fn os(&self) -> Os {
         match self {
             TargetPlatform::LinuxX86_64 => Os::Linux,
@@ -1,15 +1,34 @@
 #![cfg(test)]
 
+use crate::archive::ArchiveFormat;
+use crate::cache::CacheDirs;
+use crate::digest::{Digest, DigestAlgorithm};
+use crate::gen::{gen, GenOptions};
+use crate::rel_path::RelPathBuf;
+use crate::spec::resolve::{ResolveOptions, ResolvedSpec, ResolvedTargetSpec};
+use crate::target_platform::TargetPlatform;
+use ordinal_map::map::OrdinalMap;
+use ordinal_map::Ordinal;
+use std::fs;
+use std::fs::{File, Permissions};
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
+use zip::write::SimpleFileOptions;
+use zip::CompressionMethod;
 
 pub(crate) fn assert_shell_ok(command: impl AsRef<str>) {
     let command = command.as_ref();
     for shell in ["sh", "bash"] {
-        let status = Command::new(shell).arg("-c").arg(command)
+        let status = Command::new(shell)
+            .arg("-c")
+            .arg(command)
             .stdout(Stdio::null())
             .stderr(Stdio::null())
             .stdin(Stdio::null())
-            .status().unwrap();
+            .status()
+            .unwrap();
         assert!(status.success(), "shell {shell}, command {command} failed");
     }
 }
@@ -17,11 +36,165 @@ pub(crate) fn assert_shell_ok(command: impl AsRef<str>) {
 pub(crate) fn assert_shell_err(command: impl AsRef<str>) {
     let command = command.as_ref();
     for shell in ["sh", "bash"] {
-        let status = Command::new(shell).arg("-c").arg(command)
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .stdin(Stdio::null())
-        .status().unwrap();
-        assert!(!status.success(), "shell {shell}, command {command} succeeded");
+        let status = Command::new(shell)
+            .arg("-c")
+            .arg(command)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .stdin(Stdio::null())
+            .status()
+            .unwrap();
+        assert!(
+            !status.success(),
+            "shell {shell}, command {command} succeeded"
+        );
+    }
+}
+
+/// Write a zip archive with given `(path, content, unix mode)` entries.
+pub(crate) fn write_zip(path: &Path, entries: &[(&str, &[u8], u32)]) {
+    let mut zip = zip::ZipWriter::new(File::create(path).unwrap());
+    for (name, content, mode) in entries {
+        let options = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Stored)
+            .unix_permissions(*mode);
+        zip.start_file(*name, options).unwrap();
+        zip.write_all(content).unwrap();
+    }
+    zip.finish().unwrap();
+}
+
+/// Write a `.tar.gz` or `.tar.xz` archive, depending on the extension of `path`,
+/// with given `(path, content, unix mode)` entries.
+pub(crate) fn write_tar(path: &Path, entries: &[(&str, &[u8], u32)]) {
+    let mut tar = tar::Builder::new(Vec::new());
+    for (name, content, mode) in entries {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(*mode);
+        tar.append_data(&mut header, name, *content).unwrap();
     }
+    let tar = tar.into_inner().unwrap();
+    let file = File::create(path).unwrap();
+    match ArchiveFormat::from_file_path(path.to_str().unwrap()).unwrap() {
+        ArchiveFormat::TarGz => {
+            let mut gz = flate2::write::GzEncoder::new(file, flate2::Compression::fast());
+            gz.write_all(&tar).unwrap();
+            gz.finish().unwrap();
+        }
+        ArchiveFormat::TarXz => {
+            let mut xz =
+                lzma_rust2::XzWriter::new(file, lzma_rust2::XzOptions::with_preset(1)).unwrap();
+            xz.write_all(&tar).unwrap();
+            xz.finish().unwrap();
+        }
+        ArchiveFormat::Zip | ArchiveFormat::TarBz2 => panic!("unsupported: {}", path.display()),
+    }
+}
+
+/// Generate a script for an archive at `archive_dir/archive.zip`
+/// containing single executable file `path` which prints `ok`.
+pub(crate) fn write_test_script(
+    dir: &Path,
+    archive_dir: &Path,
+    path: &str,
+    options: &GenOptions,
+) -> PathBuf {
+    write_test_script_with_digest(dir, archive_dir, path, options, DigestAlgorithm::Sha256)
+}
+
+/// Like `write_test_script`, but pin the archive with a digest of `algorithm`.
+pub(crate) fn write_test_script_with_digest(
+    dir: &Path,
+    archive_dir: &Path,
+    path: &str,
+    options: &GenOptions,
+    algorithm: DigestAlgorithm,
+) -> PathBuf {
+    fs::create_dir_all(dir.join("home")).unwrap();
+    fs::create_dir_all(dir.join("tmp")).unwrap();
+    fs::create_dir_all(archive_dir).unwrap();
+    let archive = archive_dir.join("archive.zip");
+    write_zip(&archive, &[(path, b"#!/bin/sh\necho ok\n", 0o755)]);
+    let digest = Digest::of_file(algorithm, &archive).unwrap();
+    let size = archive.metadata().unwrap().len();
+
+    let mut spec = ResolvedSpec {
+        specs: OrdinalMap::new(),
+    };
+    for target_platform in TargetPlatform::all_values() {
+        spec.specs.insert(
+            target_platform,
+            ResolvedTargetSpec {
+                url: format!("file://{}", archive.display()),
+                size,
+                digest,
+                path: RelPathBuf::new(path.to_owned()).unwrap(),
+                strip_components: 0,
+                post_extract: Vec::new(),
+                interpreter: None,
+                interpreter_args: Vec::new(),
+                args: Vec::new(),
+                env: Vec::new(),
+                archive_format: ArchiveFormat::Zip,
+                signature: None,
+            },
+        );
+    }
+    let script = dir.join("script");
+    fs::write(&script, gen(&spec, options).unwrap()).unwrap();
+    fs::set_permissions(&script, Permissions::from_mode(0o755)).unwrap();
+    script
+}
+
+/// Cache dirs like those of scripts run with `script_command` in `dir`,
+/// with the main dir in `dir/<cache>`.
+pub(crate) fn test_cache_dirs(dir: &Path, cache: &str) -> CacheDirs {
+    CacheDirs {
+        main: dir.join(cache).join("commaslash"),
+        main_overridden: false,
+        backup: dir.join("tmp/commaslash-backup"),
+    }
+}
+
+/// Resolve `spec` of a single executable for the current platform.
+pub(crate) fn resolve_current(spec: &str, version: Option<&str>) -> ResolvedSpec {
+    let sources = OrdinalMap::from_iter([(TargetPlatform::current().unwrap(), spec.to_owned())]);
+    ResolvedSpec::resolve_sources(&sources, version, &ResolveOptions::default())
+        .unwrap()
+        .remove(0)
+}
+
+/// Resolve `spec` for the current platform and `options.version`,
+/// and write the generated script to `script`.
+pub(crate) fn write_spec_script(script: &Path, spec: &str, options: &GenOptions) -> ResolvedSpec {
+    let spec = resolve_current(spec, options.version.as_deref());
+    fs::write(script, gen(&spec, options).unwrap()).unwrap();
+    spec
+}
+
+/// Command to run a script with `HOME`, `XDG_CACHE_HOME` and `TMPDIR` inside `dir`.
+pub(crate) fn script_command(script: &Path, dir: &Path) -> Command {
+    let mut command = Command::new("sh");
+    command
+        .arg(script)
+        .current_dir(dir)
+        .env("HOME", dir.join("home"))
+        .env("XDG_CACHE_HOME", dir.join("cache"))
+        .env("TMPDIR", dir.join("tmp"));
+    command
+}
+
+/// Run a script with `script_command`, assert it succeeds, return stdout.
+pub(crate) fn run_script(script: &Path, dir: &Path, env: &[(&str, &str)]) -> String {
+    let output = script_command(script, dir)
+        .envs(env.iter().copied())
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
 }
@@ -0,0 +1,228 @@
+//! Regenerate a script for another version of the wrapped tool.
+
+use crate::gen::gen;
+use crate::github::{latest_release_tag, DEFAULT_API_URL};
+use crate::script::Script;
+use crate::spec::parse::{GithubAsset, ParsedTargetSpec};
+use crate::spec::resolve::{ResolveOptions, ResolvedSpec};
+use crate::target_platform::TargetPlatform;
+use anyhow::Context;
+use ordinal_map::map::OrdinalMap;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::PathBuf;
+
+/// Resolve specs embedded in a script generated with `--version` for another version,
+/// and rewrite the script.
+#[derive(clap::Args)]
+pub(crate) struct UpdateArgs {
+    /// Script generated by commaslash.
+    #[clap(value_name = "script")]
+    script: PathBuf,
+    /// New version, substituted for `{version}` in specs.
+    #[clap(long, value_name = "version", required_unless_present = "latest")]
+    version: Option<String>,
+    /// Use the latest release of the repository in `github=` specs.
+    #[clap(long, conflicts_with = "version")]
+    latest: bool,
+    /// GitHub API base url.
+    #[clap(long, value_name = "url", default_value = DEFAULT_API_URL)]
+    github_api_url: String,
+}
+
+pub(crate) fn update(args: &UpdateArgs) -> anyhow::Result<()> {
+    let script = Script::read(&args.script)?;
+    let resolve_options = ResolveOptions {
+        github_api_url: args.github_api_url.clone(),
+    };
+    let version = match &args.version {
+        Some(version) => version.clone(),
+        None => latest_version(&script.options.sources, &resolve_options)?,
+    };
+    let content = update_script(script, &version, &resolve_options)
+        .with_context(|| format!("Failed to update `{}`", args.script.display()))?;
+    fs::write(&args.script, content)
+        .with_context(|| format!("Failed to write `{}`", args.script.display()))?;
+    eprintln!("Updated `{}` to {version}", args.script.display());
+    Ok(())
+}
+
+fn update_script(
+    script: Script,
+    version: &str,
+    resolve_options: &ResolveOptions,
+) -> anyhow::Result<String> {
+    let Script {
+        spec: current,
+        mut options,
+    } = script;
+    if options.sources.is_empty() {
+        return Err(anyhow::anyhow!(
+            "Script has no embedded sources; generate it with `--version`"
+        ));
+    }
+    let entrypoints =
+        ResolvedSpec::resolve_sources(&options.sources, Some(version), resolve_options)?;
+    let spec = match <[ResolvedSpec; 1]>::try_from(entrypoints) {
+        Ok([spec]) => spec,
+        // Script for one of several executables of the archive.
+        Err(entrypoints) => {
+            let name = current.exe_name()?;
+            entrypoints
+                .into_iter()
+                .find(|spec| spec.exe_name().ok() == Some(name))
+                .with_context(|| format!("Sources have no executable named `{name}`"))?
+        }
+    };
+    options.version = Some(version.to_owned());
+    gen(&spec, &options)
+}
+
+/// Version of the latest release, extracted from its tag using `tag=` of the sources.
+fn latest_version(
+    sources: &OrdinalMap<TargetPlatform, String>,
+    resolve_options: &ResolveOptions,
+) -> anyhow::Result<String> {
+    let mut releases = BTreeSet::new();
+    for source in sources.values() {
+        if let Some(GithubAsset { repo, tag, .. }) = ParsedTargetSpec::parse(source)?.github {
+            releases.insert((repo, tag));
+        }
+    }
+    let mut releases = releases.into_iter();
+    let (Some((repo, tag_template)), None) = (releases.next(), releases.next()) else {
+        return Err(anyhow::anyhow!(
+            "`--latest` requires `github=` and `tag=` to be the same in all specs"
+        ));
+    };
+    let (prefix, suffix) = tag_template
+        .split_once("{version}")
+        .with_context(|| format!("`tag={tag_template}` does not contain `{{version}}`"))?;
+    let tag = latest_release_tag(&resolve_options.github_api_url, &repo)?;
+    tag.strip_prefix(prefix)
+        .and_then(|tag| tag.strip_suffix(suffix))
+        .filter(|version| !version.is_empty())
+        .map(str::to_owned)
+        .with_context(|| format!("Latest tag `{tag}` of {repo} does not match `{tag_template}`"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gen::{gen, GenOptions};
+    use crate::script::Script;
+    use crate::spec::resolve::{ResolveOptions, ResolvedSpec};
+    use crate::target_platform::TargetPlatform;
+    use crate::testutil::{run_script, write_spec_script, write_zip};
+    use crate::update::{latest_version, update_script};
+    use ordinal_map::map::OrdinalMap;
+    use std::fs;
+    use std::path::Path;
+    use tempfile::TempDir;
+
+    fn write_release(dir: &Path, version: &str) {
+        let release = dir.join("releases").join(version);
+        fs::create_dir_all(&release).unwrap();
+        let tool = format!("#!/bin/sh\necho {version}\n");
+        write_zip(
+            &release.join("tool.zip"),
+            &[("bin/tool", tool.as_bytes(), 0o755)],
+        );
+    }
+
+    #[test]
+    fn test_update() {
+        let dir = TempDir::new().unwrap();
+        write_release(dir.path(), "1.0");
+        write_release(dir.path(), "2.0");
+        let broken = dir.path().join("releases/3.0");
+        fs::create_dir_all(&broken).unwrap();
+        write_zip(&broken.join("tool.zip"), &[("tool", b"", 0o755)]);
+
+        let source = format!(
+            "url=file://{}/releases/{{version}}/tool.zip path=bin/tool",
+            dir.path().display()
+        );
+        let options = GenOptions {
+            version: Some("1.0".to_owned()),
+            sources: OrdinalMap::from_iter([(TargetPlatform::current().unwrap(), source.clone())]),
+            ..GenOptions::default()
+        };
+        let resolve_options = ResolveOptions::default();
+        let script = dir.path().join("tool");
+        write_spec_script(&script, &source, &options);
+        assert_eq!("1.0\n", run_script(&script, dir.path(), &[]));
+
+        let updated =
+            update_script(Script::read(&script).unwrap(), "2.0", &resolve_options).unwrap();
+        fs::write(&script, updated).unwrap();
+        assert_eq!("2.0\n", run_script(&script, dir.path(), &[]));
+        let updated = Script::read(&script).unwrap();
+        assert_eq!(Some("2.0"), updated.options.version.as_deref());
+        assert_eq!(options.sources.len(), updated.options.sources.len());
+
+        // No `bin/tool` in the archive.
+        assert!(update_script(updated, "3.0", &resolve_options).is_err());
+    }
+
+    #[test]
+    fn test_latest_version() {
+        let dir = TempDir::new().unwrap();
+        let latest = dir.path().join("repos/owner/tool/releases/latest");
+        fs::create_dir_all(latest.parent().unwrap()).unwrap();
+        fs::write(&latest, r#"{"tag_name": "v2.0", "assets": []}"#).unwrap();
+        let resolve_options = ResolveOptions {
+            github_api_url: format!("file://{}", dir.path().display()),
+        };
+        let sources = |tag: &str| {
+            OrdinalMap::from_iter([(
+                TargetPlatform::LinuxX86_64,
+                format!("github=owner/tool tag={tag} asset=tool-{{os}}.zip path=tool"),
+            )])
+        };
+
+        assert_eq!(
+            "2.0",
+            latest_version(&sources("v{version}"), &resolve_options).unwrap()
+        );
+        assert!(latest_version(&sources("release-{version}"), &resolve_options).is_err());
+        assert!(latest_version(&sources("v2.0"), &resolve_options).is_err());
+    }
+
+    #[test]
+    fn test_update_entrypoint() {
+        let dir = TempDir::new().unwrap();
+        for version in ["1.0", "2.0"] {
+            let release = dir.path().join("releases").join(version);
+            fs::create_dir_all(&release).unwrap();
+            let a = format!("#!/bin/sh\necho a {version}\n");
+            let b = format!("#!/bin/sh\necho b {version}\n");
+            write_zip(
+                &release.join("tool.zip"),
+                &[
+                    ("bin/a", a.as_bytes(), 0o755),
+                    ("bin/b", b.as_bytes(), 0o755),
+                ],
+            );
+        }
+        let source = format!(
+            "url=file://{}/releases/{{version}}/tool.zip path=bin/a path=bin/b",
+            dir.path().display()
+        );
+        let options = GenOptions {
+            version: Some("1.0".to_owned()),
+            sources: OrdinalMap::from_iter([(TargetPlatform::current().unwrap(), source)]),
+            ..GenOptions::default()
+        };
+        let resolve_options = ResolveOptions::default();
+        let entrypoints =
+            ResolvedSpec::resolve_sources(&options.sources, Some("1.0"), &resolve_options).unwrap();
+        let script = dir.path().join("b");
+        fs::write(&script, gen(&entrypoints[1], &options).unwrap()).unwrap();
+        assert_eq!("b 1.0\n", run_script(&script, dir.path(), &[]));
+
+        let updated =
+            update_script(Script::read(&script).unwrap(), "2.0", &resolve_options).unwrap();
+        fs::write(&script, updated).unwrap();
+        assert_eq!("b 2.0\n", run_script(&script, dir.path(), &[]));
+    }
+}